@@ -0,0 +1,70 @@
+//! A heap-free state machine for microcontrollers.
+//!
+//! Check it with `cargo check --target thumbv7em-none-eabihf`.
+
+#![no_std]
+
+use declarative_state_machine::state_machine;
+
+pub struct Pump {
+    pub cycles: u32,
+    pub pressure: u16,
+}
+
+state_machine! {
+    /// Controls a water pump
+    machine pump {
+        context: Pump;
+        max_depth: 4;
+
+        event start(_old: &mut State, _new: &mut State, pump: &mut Context) {
+            pump.cycles += 1;
+        }
+
+        event measure(
+            _old: &mut State,
+            new: &mut State,
+            pump: &mut Context,
+            queue: &mut Queue,
+            pressure: u16
+        ) {
+            pump.pressure = pressure;
+            if pressure > 900 {
+                queue.raise(Event::Stop);
+            }
+            *new = State::Running(Running::Pumping { pressure });
+        }
+
+        event stop(_old: &mut State, _new: &mut State) {}
+
+        event resume(_old: &mut State, _new: &mut State) {}
+
+        states {
+            Stopped,
+            Running: {
+                Priming,
+                Pumping { pressure: u16 },
+            },
+        }
+
+        transitions {
+            Stopped => Running : start;
+            Running => Running::Pumping : measure;
+            Running => Stopped : stop;
+            Stopped => Running[H] : resume;
+        }
+    }
+}
+
+/// Runs a pump cycle and returns whether the pump stopped because of overpressure
+pub fn cycle(pressure: u16) -> Result<bool, pump::TransitionError> {
+    let mut machine = pump::Machine::new(Pump {
+        cycles: 0,
+        pressure: 0,
+    });
+
+    machine.start()?;
+    machine.measure(pressure)?;
+
+    Ok(machine.is_stopped())
+}
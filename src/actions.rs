@@ -0,0 +1,66 @@
+use crate::error::StateMachineResult;
+use crate::events;
+use crate::diagnostic::SpanDiagnosticExt;
+
+use proc_macro2::{Ident, Span, Delimiter, TokenTree, TokenStream};
+
+pub struct Action {
+    pub state: Ident,
+    pub state_param_name: Option<Ident>,
+    pub context_param_name: Option<Ident>,
+    pub body: TokenStream,
+}
+
+pub fn parse_action(
+    iter: &mut dyn Iterator<Item = TokenTree>,
+    span: Span,
+) -> StateMachineResult<Action> {
+    let mut action = Action {
+        state: Ident::new("__invalid__", span),
+        state_param_name: None,
+        context_param_name: None,
+        body: TokenStream::new(),
+    };
+
+    let mut span = span;
+    let mut next = iter.next();
+
+    if let Some(TokenTree::Ident(ident)) = next.clone() {
+        span = ident.span();
+        action.state = ident;
+        next = iter.next();
+    } else {
+        return Err(span.error("expected state name").into());
+    }
+
+    if let Some(TokenTree::Group(group)) = next.clone() {
+        if let Delimiter::Parenthesis = group.delimiter() {
+            span = group.span();
+            for param in events::parse_payload(group.stream())? {
+                if events::is_state_param(&param) && action.state_param_name.is_none() {
+                    action.state_param_name = Some(param.name);
+                } else if events::is_context_param(&param) && action.context_param_name.is_none() {
+                    action.context_param_name = Some(param.name);
+                } else {
+                    return Err(param.name.span().error(
+                        "expected at most one '&mut State' and one '&mut Context' parameter"
+                    ).into());
+                }
+            }
+            next = iter.next();
+        }
+    }
+
+    if let Some(TokenTree::Group(group)) = next.clone() {
+        if let Delimiter::Brace = group.delimiter() {
+            action.body = group.stream();
+            return Ok(action);
+        }
+    }
+
+    if let Some(next) = next {
+        span = next.span();
+    }
+
+    Err(span.error("expected action body").into())
+}
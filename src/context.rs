@@ -0,0 +1,41 @@
+use crate::error::{StateMachineError, StateMachineResult};
+use crate::diagnostic::SpanDiagnosticExt;
+
+use proc_macro2::{TokenTree, TokenStream, Span};
+use syn::Type;
+
+pub fn parse_context(
+    iter: &mut dyn Iterator<Item = TokenTree>,
+    mut span: Span,
+) -> StateMachineResult<Type> {
+    if let Some(next) = iter.next() {
+        span = next.span();
+        if let TokenTree::Punct(punct) = next {
+            if punct.as_char() == ':' {
+                let mut tokens = TokenStream::new();
+
+                for token in iter {
+                    span = token.span();
+                    if let TokenTree::Punct(punct) = &token {
+                        if punct.as_char() == ';' {
+                            if tokens.is_empty() {
+                                return Err(span.error("expected context type").into());
+                            }
+
+                            return syn::parse2(tokens)
+                                .map_err::<StateMachineError, _>(
+                                    |err| err.span().error(format!("{}", err)).into()
+                                );
+                        }
+                    }
+
+                    tokens.extend(Some(token));
+                }
+
+                return Err(span.error("expected semicolon ';' after context type").into());
+            }
+        }
+    }
+
+    Err(span.error("expected context definition 'context: Type;'").into())
+}
@@ -0,0 +1,235 @@
+use crate::error::{StateMachineError, StateMachineResult};
+
+use std::cell::RefCell;
+use proc_macro2::{Span, TokenStream};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Error,
+    Warning,
+    Note,
+    Help,
+}
+
+/// A compiler diagnostic. With the `nightly` feature it is emitted through the unstable
+/// `proc_macro::Diagnostic` API, otherwise it is rendered as a `compile_error!` invocation or
+/// as the use of a deprecated item for warnings.
+pub struct Diagnostic {
+    level: Level,
+    span: Span,
+    message: String,
+    children: Vec<(Level, Option<Span>, String)>,
+}
+
+thread_local! {
+    static EMITTED: RefCell<Vec<Diagnostic>> = const { RefCell::new(Vec::new()) };
+}
+
+pub trait SpanDiagnosticExt {
+    fn error<T: Into<String>>(self, message: T) -> Diagnostic;
+    fn warning<T: Into<String>>(self, message: T) -> Diagnostic;
+}
+
+impl SpanDiagnosticExt for Span {
+    fn error<T: Into<String>>(self, message: T) -> Diagnostic {
+        Diagnostic::spanned(self, Level::Error, message)
+    }
+
+    fn warning<T: Into<String>>(self, message: T) -> Diagnostic {
+        Diagnostic::spanned(self, Level::Warning, message)
+    }
+}
+
+impl Diagnostic {
+    pub fn spanned<T: Into<String>>(span: Span, level: Level, message: T) -> Self {
+        Self {
+            level,
+            span,
+            message: message.into(),
+            children: Vec::new(),
+        }
+    }
+
+    pub fn help<T: Into<String>>(mut self, message: T) -> Self {
+        self.children.push((Level::Help, None, message.into()));
+        self
+    }
+
+    pub fn span_note<T: Into<String>>(mut self, span: Span, message: T) -> Self {
+        self.children.push((Level::Note, Some(span), message.into()));
+        self
+    }
+
+    /// Queues the diagnostic to be reported along with the macro output by `report`
+    pub fn emit(self) {
+        EMITTED.with(|emitted| emitted.borrow_mut().push(self));
+    }
+
+    #[cfg(feature = "nightly")]
+    fn report(self) -> TokenStream {
+        let level = match self.level {
+            Level::Error => proc_macro::Level::Error,
+            Level::Warning => proc_macro::Level::Warning,
+            Level::Note => proc_macro::Level::Note,
+            Level::Help => proc_macro::Level::Help,
+        };
+        let mut diagnostic = proc_macro::Diagnostic::spanned(self.span.unwrap(), level, self.message);
+
+        for (child, span, message) in self.children {
+            diagnostic = match (child, span) {
+                (Level::Help, Some(span)) => diagnostic.span_help(span.unwrap(), message),
+                (Level::Help, None) => diagnostic.help(message),
+                (_, Some(span)) => diagnostic.span_note(span.unwrap(), message),
+                (_, None) => diagnostic.note(message),
+            };
+        }
+
+        diagnostic.emit();
+
+        TokenStream::new()
+    }
+
+    /// Children are appended to the message, as a separate diagnostic for a note would count as
+    /// another error and identical diagnostics are only reported once by rustc
+    #[cfg(not(feature = "nightly"))]
+    fn report(self) -> TokenStream {
+        let mut message = self.message;
+
+        for (child, span, child_message) in self.children {
+            let prefix = match child {
+                Level::Help => "help",
+                _ => "note",
+            };
+
+            match span {
+                Some(span) => {
+                    let span = span.unwrap();
+                    message.push_str(&format!(
+                        "\n  = {}: {} (line {}, column {})",
+                        prefix,
+                        child_message,
+                        span.line(),
+                        span.column(),
+                    ));
+                },
+                None => message.push_str(&format!("\n  = {}: {}", prefix, child_message)),
+            }
+        }
+
+        match self.level {
+            Level::Error => compile_error(self.span, &message),
+            _ => deprecation_warning(self.span, &message),
+        }
+    }
+}
+
+/// `compile_error!("message");` with all tokens pointing at `span`
+#[cfg(not(feature = "nightly"))]
+fn compile_error(span: Span, message: &str) -> TokenStream {
+    use proc_macro2::{TokenTree, Ident, Punct, Spacing, Literal, Group, Delimiter};
+
+    let mut literal = Literal::string(message);
+    literal.set_span(span);
+    let stream = Some(TokenTree::from(literal)).into_iter().collect();
+    let mut group = Group::new(Delimiter::Parenthesis, stream);
+    group.set_span(span);
+    let mut bang = Punct::new('!', Spacing::Alone);
+    bang.set_span(span);
+    let mut semicolon = Punct::new(';', Spacing::Alone);
+    semicolon.set_span(span);
+
+    vec![
+        TokenTree::from(Ident::new("compile_error", span)),
+        TokenTree::from(bang),
+        TokenTree::from(group),
+        TokenTree::from(semicolon),
+    ].into_iter().collect()
+}
+
+/// Stable Rust cannot emit warnings from procedural macros, so a deprecated item is used at
+/// `span`, which makes the compiler report `message` as a deprecation warning there
+#[cfg(not(feature = "nightly"))]
+fn deprecation_warning(span: Span, message: &str) -> TokenStream {
+    use proc_macro2::Ident;
+    use quote::quote;
+
+    let warning = Ident::new("state_machine_warning", span);
+
+    quote! {
+        const _: () = {
+            #[deprecated(note = #message)]
+            #[allow(non_camel_case_types)]
+            struct state_machine_warning;
+
+            let _ = #warning;
+        };
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, a) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous + if a == *b { 0 } else { 1 };
+            previous = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(previous + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+/// The candidate most similar to `name` for a "did you mean" suggestion. Candidates differing
+/// in case only are preferred, otherwise the edit distance may be at most a third of the length
+/// of `name`.
+pub fn similar_name<T: ToString>(name: &str, candidates: &[T]) -> Option<String> {
+    let candidates = candidates.iter()
+        .map(|candidate| candidate.to_string())
+        .filter(|candidate| candidate != name)
+        .collect::<Vec<_>>();
+
+    if let Some(candidate) = candidates.iter()
+        .find(|candidate| candidate.to_lowercase() == name.to_lowercase())
+    {
+        return Some(candidate.clone());
+    }
+
+    let max_distance = std::cmp::max(name.chars().count() / 3, 1);
+
+    candidates.into_iter()
+        .map(|candidate| (edit_distance(name, &candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Whether an error has been emitted, which must fail the macro invocation
+pub fn has_errors() -> bool {
+    EMITTED.with(|emitted| {
+        emitted.borrow().iter().any(|diagnostic| diagnostic.level == Level::Error)
+    })
+}
+
+/// Fails with `StateMachineError::Emitted` if an error has been emitted
+pub fn abort_if_errors() -> StateMachineResult<()> {
+    match has_errors() {
+        true => Err(StateMachineError::Emitted),
+        false => Ok(()),
+    }
+}
+
+/// Reports `diagnostic` and all diagnostics queued by `Diagnostic::emit`. The returned tokens
+/// must be part of the macro output on stable Rust.
+pub fn report(diagnostic: Option<Diagnostic>) -> TokenStream {
+    let mut diagnostics = EMITTED.with(|emitted| emitted.replace(Vec::new()));
+    diagnostics.extend(diagnostic);
+
+    diagnostics.into_iter()
+        .map(Diagnostic::report)
+        .collect()
+}
@@ -0,0 +1,321 @@
+use crate::syntax::Machine;
+use crate::regions::Region;
+use crate::states::States;
+use crate::transitions::{Transition, Source, History};
+
+use std::fmt::Write;
+use proc_macro2::Ident;
+
+/// The states a transition is drawn from. Wildcards are drawn from the outermost states which
+/// do not contain any excluded state.
+fn sources(states: &States, source: &Source) -> Vec<Ident> {
+    fn expand(states: &States, names: &[Ident], except: &[Ident], sources: &mut Vec<Ident>) {
+        for name in names {
+            if except.iter().any(|excluded| states.contains(name, excluded)) {
+                let state = states.get(name).unwrap();
+                expand(states, &state.children, except, sources);
+            } else {
+                sources.push(name.clone());
+            }
+        }
+    }
+
+    match source {
+        Source::States(paths) => paths.iter()
+            .map(|path| path.last().unwrap().clone())
+            .collect(),
+        Source::Wildcard(except) => {
+            let roots = states.states.iter()
+                .filter(|state| state.parent.is_none())
+                .map(|state| state.name.clone())
+                .collect::<Vec<_>>();
+            let except = except.iter()
+                .map(|path| path.last().unwrap().clone())
+                .collect::<Vec<_>>();
+            let mut sources = Vec::new();
+
+            expand(states, &roots, &except, &mut sources);
+            sources
+        },
+    }
+}
+
+/// The label of a transition edge, e.g. `run [battery_ok()]`
+fn label(event: &Ident, transition: &Transition, history: bool) -> String {
+    let mut label = event.to_string();
+
+    if let Some(guard) = &transition.guard {
+        write!(label, " [{}]", guard).unwrap();
+    }
+
+    if history {
+        match transition.history {
+            Some(History::Shallow) => label.push_str(" (H)"),
+            Some(History::Deep) => label.push_str(" (H*)"),
+            None => {},
+        }
+    }
+
+    label
+}
+
+/// All transitions of `region` as `(event, from, transition)` in the order of the event
+/// declarations
+fn edges<'a>(machine: &Machine, region: &'a Region) -> Vec<(Ident, Ident, &'a Transition)> {
+    let mut edges = Vec::new();
+
+    for event in machine.events.iter() {
+        for transition in region.transitions.get(&event.name).into_iter().flatten() {
+            for from in sources(&region.states, &transition.from) {
+                edges.push((event.name.clone(), from, transition));
+            }
+        }
+    }
+
+    edges
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn dot_states(out: &mut String, states: &States, names: &[Ident], indent: usize) {
+    let pad = "    ".repeat(indent);
+
+    for name in names {
+        let state = states.get(name).unwrap();
+
+        if state.is_composite() {
+            writeln!(out, "{}subgraph cluster_{} {{", pad, name).unwrap();
+            writeln!(out, "{}    label=\"{}\";", pad, name).unwrap();
+            writeln!(out, "{}    __start_{} [shape=point];", pad, name).unwrap();
+            writeln!(out, "{}    __start_{} -> {};", pad, name, states.initial_leaf(&state.children[0]))
+                .unwrap();
+            dot_states(out, states, &state.children, indent + 1);
+            writeln!(out, "{}}}", pad).unwrap();
+        } else {
+            writeln!(out, "{}{} [shape=box, style=rounded];", pad, name).unwrap();
+        }
+    }
+}
+
+/// Renders the machine as a Graphviz DOT digraph. Composite states and named regions are
+/// drawn as clusters, edges into or out of a composite state end at its border.
+pub fn dot(machine: &Machine) -> String {
+    let mut out = String::new();
+
+    writeln!(out, "digraph {} {{", machine.name).unwrap();
+    writeln!(out, "    compound=true;").unwrap();
+
+    for (index, region) in machine.regions.iter().enumerate() {
+        let states = &region.states;
+        let roots = states.states.iter()
+            .filter(|state| state.parent.is_none())
+            .map(|state| state.name.clone())
+            .collect::<Vec<_>>();
+        let mut indent = 1;
+
+        if let Some(name) = &region.name {
+            writeln!(out, "    subgraph cluster_region_{} {{", name).unwrap();
+            writeln!(out, "        label=\"{}\";", name).unwrap();
+            writeln!(out, "        style=dashed;").unwrap();
+            indent += 1;
+        }
+
+        let pad = "    ".repeat(indent);
+        writeln!(out, "{}__start_{} [shape=point];", pad, index).unwrap();
+        dot_states(&mut out, states, &roots, indent);
+
+        if region.name.is_some() {
+            writeln!(out, "    }}").unwrap();
+        }
+
+        writeln!(out, "    __start_{} -> {};", index, states.initial_leaf(&states.initial)).unwrap();
+
+        for (event, from, transition) in edges(machine, region) {
+            let to = &transition.to;
+            let label = label(&event, transition, true);
+            let mut attributes = vec![format!("label=\"{}\"", escape(&label))];
+
+            if states.get(&from).map(|state| state.is_composite()).unwrap_or(false) {
+                attributes.push(format!("ltail=cluster_{}", from));
+            }
+            if states.get(to).map(|state| state.is_composite()).unwrap_or(false) {
+                attributes.push(format!("lhead=cluster_{}", to));
+            }
+
+            writeln!(
+                out,
+                "    {} -> {} [{}];",
+                states.initial_leaf(&from),
+                states.initial_leaf(to),
+                attributes.join(", "),
+            ).unwrap();
+        }
+    }
+
+    writeln!(out, "}}").unwrap();
+
+    out
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Notation {
+    Mermaid,
+    PlantUml,
+}
+
+/// Declares all states inside of their parents, so states without transitions are drawn as well
+fn uml_states(
+    out: &mut String,
+    states: &States,
+    names: &[Ident],
+    notation: Notation,
+    indent: usize,
+) {
+    let pad = "    ".repeat(indent);
+
+    for name in names {
+        let state = states.get(name).unwrap();
+
+        if state.is_composite() {
+            writeln!(out, "{}state {} {{", pad, name).unwrap();
+            writeln!(out, "{}    [*] --> {}", pad, state.children[0]).unwrap();
+            uml_states(out, states, &state.children, notation, indent + 1);
+            writeln!(out, "{}}}", pad).unwrap();
+        } else {
+            match notation {
+                Notation::Mermaid => writeln!(out, "{}{}", pad, name).unwrap(),
+                Notation::PlantUml => writeln!(out, "{}state {}", pad, name).unwrap(),
+            }
+        }
+    }
+}
+
+fn uml_region(out: &mut String, machine: &Machine, region: &Region, notation: Notation, indent: usize) {
+    let pad = "    ".repeat(indent);
+    let states = &region.states;
+    let roots = states.states.iter()
+        .filter(|state| state.parent.is_none())
+        .map(|state| state.name.clone())
+        .collect::<Vec<_>>();
+
+    writeln!(out, "{}[*] --> {}", pad, states.initial).unwrap();
+    uml_states(out, states, &roots, notation, indent);
+
+    for (event, from, transition) in edges(machine, region) {
+        let label = label(&event, transition, notation == Notation::Mermaid);
+        let to = match (notation, transition.history) {
+            (Notation::PlantUml, Some(History::Shallow)) => format!("{}[H]", transition.to),
+            (Notation::PlantUml, Some(History::Deep)) => format!("{}[H*]", transition.to),
+            _ => transition.to.to_string(),
+        };
+
+        writeln!(out, "{}{} --> {} : {}", pad, from, to, label).unwrap();
+    }
+}
+
+/// Renders the machine as a UML state diagram. Regions are drawn as concurrent parts of a
+/// state named after the machine.
+fn uml(machine: &Machine, notation: Notation) -> String {
+    let mut out = String::new();
+
+    match notation {
+        Notation::Mermaid => writeln!(out, "stateDiagram-v2").unwrap(),
+        Notation::PlantUml => writeln!(out, "@startuml").unwrap(),
+    }
+
+    if machine.regions[0].name.is_none() {
+        uml_region(&mut out, machine, &machine.regions[0], notation, 1);
+    } else {
+        writeln!(out, "    [*] --> {}", machine.name).unwrap();
+        writeln!(out, "    state {} {{", machine.name).unwrap();
+
+        for (index, region) in machine.regions.iter().enumerate() {
+            if index > 0 {
+                writeln!(out, "        --").unwrap();
+            }
+
+            uml_region(&mut out, machine, region, notation, 2);
+        }
+
+        writeln!(out, "    }}").unwrap();
+    }
+
+    if notation == Notation::PlantUml {
+        writeln!(out, "@enduml").unwrap();
+    }
+
+    out
+}
+
+/// Renders the machine as a Mermaid `stateDiagram-v2`
+pub fn mermaid(machine: &Machine) -> String {
+    uml(machine, Notation::Mermaid)
+}
+
+/// Renders the machine as a PlantUML state diagram
+pub fn plantuml(machine: &Machine) -> String {
+    uml(machine, Notation::PlantUml)
+}
+
+/// Describes a single transition, e.g. `Paused → Running [if battery_ok()]`
+fn describe(from: &Ident, transition: &Transition) -> String {
+    let mut text = format!("{} → {}", from, transition.to);
+
+    match transition.history {
+        Some(History::Shallow) => text.push_str(" (H)"),
+        Some(History::Deep) => text.push_str(" (H*)"),
+        None => {},
+    }
+
+    if let Some(guard) = &transition.guard {
+        write!(text, " [if {}]", guard).unwrap();
+    }
+
+    text
+}
+
+/// Describes all transitions of `event`, e.g. `` `run`: Stopped → Running, Paused → Running ``
+pub fn describe_event(machine: &Machine, event: &Ident) -> String {
+    let transitions = machine.regions.iter()
+        .flat_map(|region| {
+            region.transitions.get(event).into_iter()
+                .flatten()
+                .flat_map(move |transition| {
+                    sources(&region.states, &transition.from).into_iter()
+                        .map(move |from| describe(&from, transition))
+                })
+        })
+        .collect::<Vec<_>>();
+
+    match transitions.is_empty() {
+        true => format!("`{}`: no transitions", event),
+        false => format!("`{}`: {}", event, transitions.join(", ")),
+    }
+}
+
+/// Describes the transitions leaving and entering `state` as separate lines
+pub fn describe_state(machine: &Machine, region: &Region, state: &Ident) -> Vec<String> {
+    let mut leaving = Vec::new();
+    let mut entering = Vec::new();
+
+    for (event, from, transition) in edges(machine, region) {
+        if from == *state {
+            leaving.push(format!("`{}`: {}", event, describe(&from, transition)));
+        }
+        if transition.to == *state {
+            entering.push(format!("`{}`: {}", event, describe(&from, transition)));
+        }
+    }
+
+    let mut lines = Vec::new();
+    if !leaving.is_empty() {
+        lines.push(format!("Leaves via {}.", leaving.join(", ")));
+    }
+    if !entering.is_empty() {
+        lines.push(format!("Entered via {}.", entering.join(", ")));
+    }
+
+    lines
+}
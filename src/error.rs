@@ -1,8 +1,24 @@
-use proc_macro::Diagnostic;
+use crate::diagnostic::Diagnostic;
 
 pub enum StateMachineError {
     NoFurtherTokens,
     CompilationFailure(Diagnostic),
+    /// All errors have been emitted already, see `StateMachineError::recover`
+    Emitted,
+}
+
+impl StateMachineError {
+    /// Emits the diagnostic of a compilation failure, so parsing can continue with the next
+    /// definition. The error is reported along with all other diagnostics once parsing is done.
+    pub fn recover(self) -> StateMachineResult<()> {
+        match self {
+            StateMachineError::CompilationFailure(diagnostic) => {
+                diagnostic.emit();
+                Ok(())
+            },
+            err => Err(err),
+        }
+    }
 }
 
 impl From<Diagnostic> for StateMachineError {
@@ -1,8 +1,13 @@
-use crate::error::StateMachineResult;
+use crate::error::{StateMachineError, StateMachineResult};
+use crate::diagnostic::SpanDiagnosticExt;
 use proc_macro2::{Ident, Span, Delimiter, TokenTree, TokenStream};
+use quote::quote;
+use syn::{Token, Type, Attribute};
+use syn::parse::{Parse, ParseStream, Parser};
+use syn::punctuated::Punctuated;
 
 fn parse_event_param(
-    iter: &mut Iterator<Item = TokenTree>,
+    iter: &mut dyn Iterator<Item = TokenTree>,
     span: Span,
 ) -> StateMachineResult<(Ident, Span)> {
     Ok(span)
@@ -14,7 +19,7 @@ fn parse_event_param(
                 }
             }
 
-            return Err(span.unwrap().error("expected identifier").into());
+            Err(span.error("expected identifier").into())
         })
         .and_then(|(param, mut span)| {
             if let Some(colon) = iter.next() {
@@ -26,7 +31,7 @@ fn parse_event_param(
                 }
             }
 
-            return Err(span.unwrap().error("expected colon ':'").into());
+            Err(span.error("expected colon ':'").into())
         })
         .and_then(|(param, mut span)| {
             if let Some(reference) = iter.next() {
@@ -38,31 +43,31 @@ fn parse_event_param(
                 }
             }
 
-            return Err(span.unwrap().error("expected mutable reference '&mut'").into());
+            Err(span.error("expected mutable reference '&mut'").into())
         })
         .and_then(|(param, mut span)| {
             if let Some(mut_kw) = iter.next() {
                 span = mut_kw.span();
                 if let TokenTree::Ident(ident) = mut_kw {
-                    if ident.to_string() == "mut" {
+                    if ident == "mut" {
                         return Ok((param, span));
                     }
                 }
             }
 
-            return Err(span.unwrap().error("expected mutable reference '&mut'").into());
+            Err(span.error("expected mutable reference '&mut'").into())
         })
         .and_then(|(param, mut span)| {
             if let Some(typename) = iter.next() {
                 span = typename.span();
                 if let TokenTree::Ident(ident) = typename {
-                    if ident.to_string() == "State" {
+                    if ident == "State" {
                         return Ok((param, span));
                     }
                 }
             }
 
-            return Err(span.unwrap().error("expected 'State' type").into());
+            Err(span.error("expected 'State' type").into())
         })
 }
 
@@ -79,24 +84,168 @@ fn is_comma(comma_option: Option<TokenTree>, mut span: Span) -> Option<Span> {
     Some(span)
 }
 
+pub struct Param {
+    pub name: Ident,
+    pub ty: Type,
+}
+
+impl Parse for Param {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name = input.parse()?;
+        input.parse::<Token![:]>()?;
+        let ty = input.parse()?;
+
+        Ok(Param {
+            name,
+            ty,
+        })
+    }
+}
+
+pub fn parse_payload(params: TokenStream) -> StateMachineResult<Vec<Param>> {
+    let params = Punctuated::<Param, Token![,]>::parse_terminated
+        .parse2(params)
+        .map_err::<StateMachineError, _>(
+            |err| err.span().error(format!("{}", err)).into()
+        )?;
+
+    Ok(params.into_iter().collect())
+}
+
+fn is_mut_ref(param: &Param, name: &str) -> bool {
+    if let Type::Reference(reference) = &param.ty {
+        if let Type::Path(path) = reference.elem.as_ref() {
+            return reference.mutability.is_some()
+                && path.qself.is_none()
+                && path.path.is_ident(name);
+        }
+    }
+
+    false
+}
+
+pub fn is_context_param(param: &Param) -> bool {
+    is_mut_ref(param, "Context")
+}
+
+pub fn is_state_param(param: &Param) -> bool {
+    is_mut_ref(param, "State")
+}
+
+pub fn is_queue_param(param: &Param) -> bool {
+    is_mut_ref(param, "Queue")
+}
+
 pub struct Event {
+    pub attributes: Vec<Attribute>,
     pub name: Ident,
     pub old_param_name: Ident,
     pub new_param_name: Ident,
+    pub context_param_name: Option<Ident>,
+    pub queue_param_name: Option<Ident>,
+    pub payload: Vec<Param>,
     pub body: TokenStream,
 }
 
-pub fn parse_event(iter: &mut Iterator<Item = TokenTree>, span: Span) -> StateMachineResult<Event> {
+/// Whether `tokens` contain a reference or a lifetime other than `'static`
+fn is_borrowed(tokens: TokenStream) -> bool {
+    let tokens = tokens.into_iter().collect::<Vec<_>>();
+
+    tokens.iter().enumerate().any(|(index, token)| match token {
+        TokenTree::Group(group) => is_borrowed(group.stream()),
+        TokenTree::Punct(punct) if punct.as_char() == '&' => match tokens.get(index + 1) {
+            Some(TokenTree::Punct(lifetime)) => lifetime.as_char() != '\'',
+            _ => true,
+        },
+        TokenTree::Punct(punct) if punct.as_char() == '\'' => match tokens.get(index + 1) {
+            Some(TokenTree::Ident(ident)) => ident != "static",
+            _ => true,
+        },
+        _ => false,
+    })
+}
+
+fn is_attribute(attribute: &Attribute, names: &[&str]) -> bool {
+    names.iter().any(|name| attribute.path.is_ident(name))
+}
+
+impl Event {
+    /// The name of the generated `Event` variant, e.g. `SetSpeed` for `set_speed`
+    pub fn variant_name(&self) -> Ident {
+        let name = self.name.to_string();
+        let variant = name
+            .split('_')
+            .map(|word| {
+                let mut chars = word.chars();
+                match chars.next() {
+                    Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+                    None => String::new(),
+                }
+            })
+            .collect::<String>();
+
+        match variant.is_empty() {
+            true => self.name.clone(),
+            false => Ident::new(&variant, self.name.span()),
+        }
+    }
+
+    /// Whether the payload can be stored in the generated `Event` enum, which is not the case
+    /// for borrowed payloads like `&str`
+    pub fn is_dispatchable(&self) -> bool {
+        !self.payload.iter().any(|param| {
+            let ty = &param.ty;
+            is_borrowed(quote! { #ty })
+        })
+    }
+
+    /// Conditional compilation attributes, which apply to every item generated for this event
+    pub fn cfg_attributes(&self) -> TokenStream {
+        let attributes = self.attributes.iter()
+            .filter(|attribute| is_attribute(attribute, &["cfg", "cfg_attr"]));
+
+        quote! { #(#attributes)* }
+    }
+
+    /// Attributes forwarded to the generated event method
+    pub fn method_attributes(&self) -> TokenStream {
+        let attributes = self.attributes.iter()
+            .filter(|attribute| !is_attribute(attribute, &["cfg", "cfg_attr", "serde"]));
+
+        quote! { #(#attributes)* }
+    }
+
+    /// Attributes forwarded to the generated `Event` variant
+    pub fn variant_attributes(&self) -> TokenStream {
+        let attributes = self.attributes.iter()
+            .filter(|attribute| is_attribute(attribute, &["doc", "deprecated", "serde"]));
+
+        quote! { #(#attributes)* }
+    }
+}
+
+pub fn parse_attributes(tokens: TokenStream) -> StateMachineResult<Vec<Attribute>> {
+    Attribute::parse_outer
+        .parse2(tokens)
+        .map_err::<StateMachineError, _>(
+            |err| err.span().error(format!("{}", err)).into()
+        )
+}
+
+pub fn parse_event(
+    iter: &mut dyn Iterator<Item = TokenTree>,
+    span: Span,
+) -> StateMachineResult<Event> {
     iter.next()
-        .ok_or(span.unwrap().error("expected event name").into())
+        .ok_or(span.error("expected event name").into())
         .and_then(|next| {
             let span = next.span();
             if let TokenTree::Ident(name) = next {
-                return iter.next()
-                    .ok_or(span.unwrap().error("missing event signature").into())
-                    .map(|next| (name, next));
+                iter.next()
+                    .ok_or(span.error("missing event signature").into())
+                    .map(|next| (name, next))
             } else {
-                return Err(span.unwrap().error("expected event name").into());
+                Err(span.error("expected event name").into())
             }
         })
         .and_then(|(name, next)| {
@@ -109,48 +258,69 @@ pub fn parse_event(iter: &mut Iterator<Item = TokenTree>, span: Span) -> StateMa
                     let (old_state, span) = parse_event_param(&mut param_iter, span)?;
 
                     if let Some(span) = is_comma(param_iter.next(), span) {
-                        return Err(span.unwrap().error("expected second parameter").into());
+                        return Err(span.error("expected second parameter").into());
                     }
 
                     let (new_state, mut span) = parse_event_param(&mut param_iter, span)?;
 
-                    let result = iter.next()
-                        .ok_or(span.unwrap().error("missing event body").into())
-                        .map(|next| (name, old_state, new_state, next));
+                    let mut payload = Vec::new();
 
                     if let Some(comma) = param_iter.next() {
                         span = comma.span();
                         if let TokenTree::Punct(punct) = comma {
                             if punct.as_char() == ',' {
-                                return result;
+                                payload = parse_payload(param_iter.by_ref().collect())?;
+                            } else {
+                                return Err(span.error("expected comma ','").into());
                             }
                         } else {
-                            return Err(span.unwrap().error("only two arguments expected").into());
+                            return Err(span.error("expected comma ','").into());
+                        }
+                    }
+
+                    // the context and the event queue may precede the payload in any order
+                    let mut context = None;
+                    let mut queue = None;
+                    loop {
+                        match payload.first() {
+                            Some(param) if is_context_param(param) && context.is_none() => {
+                                context = Some(payload.remove(0).name);
+                            },
+                            Some(param) if is_queue_param(param) && queue.is_none() => {
+                                queue = Some(payload.remove(0).name);
+                            },
+                            _ => break,
                         }
                     }
 
-                    return result;
+                    return iter.next()
+                        .ok_or(span.error("missing event body").into())
+                        .map(|next| (name, old_state, new_state, (context, queue), payload, next));
                 }
             }
 
-            return Err(span.unwrap().error(
-                "expected event signature '(old: &mut State, new: &mut State)'"
-            ).into());
+            Err(span.error(
+                "expected event signature '(old: &mut State, new: &mut State, ...)'"
+            ).into())
         })
-        .and_then(|(name, old_state, new_state, next)| {
+        .and_then(|(name, old_state, new_state, (context, queue), payload, next)| {
             let mut span = next.span();
             if let TokenTree::Group(group) = next {
                 span = group.span();
                 if let Delimiter::Brace = group.delimiter() {
                     return Ok(Event {
-                        name: name,
+                        attributes: Vec::new(),
+                        name,
                         old_param_name: old_state,
                         new_param_name: new_state,
+                        context_param_name: context,
+                        queue_param_name: queue,
+                        payload,
                         body: group.stream(),
                     });
                 }
             }
 
-            return Err(span.unwrap().error("expected event body").into());
+            Err(span.error("expected event body").into())
         })
 }
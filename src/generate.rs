@@ -1,43 +1,387 @@
 use crate::syntax::Machine;
-use proc_macro2::{Ident, TokenStream};
+use crate::actions::Action;
+use crate::regions::Region;
+use crate::states::State;
+use crate::transitions::{History, by_precedence};
+use crate::typestate;
+use crate::diagram;
+use proc_macro2::{Ident, Span, TokenStream};
 use quote::quote;
 
 struct Prepared {
     name: Ident,
 
+    state_variants: Vec<TokenStream>,
     state_idents: Vec<Ident>,
-    state_defaults: Vec<TokenStream>,
-    state_initial: Ident,
-    state_definitions: TokenStream,
-    state_names: Vec<Ident>,
+    state_parents: Vec<TokenStream>,
+    state_paths: Vec<String>,
+    state_predicates: Vec<Ident>,
+    state_initials: Vec<Ident>,
+    state_cfgs: Vec<TokenStream>,
+
+    region_initials: Vec<Ident>,
+    region_accessors: Vec<TokenStream>,
+
+    composite_names: Vec<Ident>,
+    composite_variants: Vec<TokenStream>,
+    composite_indices: Vec<TokenStream>,
+    composite_initials: Vec<Ident>,
+    composite_cfgs: Vec<TokenStream>,
+    composite_count: TokenStream,
+
+    leaf_idents: Vec<Ident>,
+    leaf_indices: Vec<TokenStream>,
+    leaf_defaults: Vec<TokenStream>,
+    leaf_patterns: Vec<TokenStream>,
+    leaf_cfgs: Vec<TokenStream>,
+    leaf_count: TokenStream,
+
+    region_indices: Vec<usize>,
+    region_leaves: Vec<TokenStream>,
+
+    serde_derive: TokenStream,
+    std_error_transition: TokenStream,
+    std_error_restore: TokenStream,
+
+    context_type: TokenStream,
+    context_param: TokenStream,
+    context_value: TokenStream,
+
+    max_depth: usize,
+
+    enter_actions: TokenStream,
+    exit_actions: TokenStream,
 
     handler_names: Vec<Ident>,
     handler_old_param_names: Vec<Ident>,
     handler_new_param_names: Vec<Ident>,
+    handler_context_param_names: Vec<Ident>,
+    handler_queue_param_names: Vec<Ident>,
+    handler_payloads: Vec<TokenStream>,
     handler_bodies: Vec<TokenStream>,
 
     event_names: Vec<Ident>,
-    event_handlers: Vec<Ident>,
+    event_dispatchable: Vec<bool>,
+    event_strings: Vec<String>,
+    event_variants: Vec<Ident>,
+    event_fields: Vec<TokenStream>,
+    event_patterns: Vec<TokenStream>,
+    event_arguments: Vec<TokenStream>,
+    event_processors: Vec<Ident>,
+    event_docs: Vec<String>,
+    event_cfgs: Vec<TokenStream>,
+    event_method_attributes: Vec<TokenStream>,
+    event_variant_attributes: Vec<TokenStream>,
+
     event_transitions: Vec<TokenStream>,
 }
 
-fn prepare<'a>(machine: Machine) -> Prepared {
+fn prepare_actions(machine: &Machine, actions: &[Action], kind: &str) -> TokenStream {
+    let dispatcher = Ident::new(&format!("run_{}_action", kind), Span::call_site());
+    let (idents, names): (Vec<_>, Vec<_>) = actions.iter()
+        .map(|action| (
+            action.state.clone(),
+            Ident::new(
+                &format!("on_{}_{}", kind, action.state.to_string().to_lowercase()),
+                action.state.span(),
+            ),
+        ))
+        .unzip();
+    let state_param_names = actions.iter()
+        .map(|action| action.state_param_name.clone()
+            .unwrap_or_else(|| Ident::new("_state", action.state.span())));
+    let context_param_names = actions.iter()
+        .map(|action| action.context_param_name.clone()
+            .unwrap_or_else(|| Ident::new("_context", action.state.span())));
+    let bodies = actions.iter()
+        .map(|action| action.body.clone());
+    let cfgs = actions.iter()
+        .map(|action| state_cfg(machine, &action.state))
+        .collect::<Vec<_>>();
+    let cfgs2 = cfgs.clone();
+    let names2 = names.clone();
+
+    quote! {
+        #(
+            #cfgs
+            fn #names(#state_param_names: &mut State, #context_param_names: &mut Context) {
+                #bodies
+            }
+        )*
+
+        #[allow(unreachable_patterns)]
+        fn #dispatcher(ident: StateIdentifier, state: &mut State, context: &mut Context) {
+            match ident {
+                #(#cfgs2 StateIdentifier::#idents => Self::#names2(state, context),)*
+                _ => {},
+            }
+        }
+    }
+}
+
+/// The `#[cfg(...)]` attribute of the state `name` in any region
+fn state_cfg(machine: &Machine, name: &Ident) -> TokenStream {
+    machine.regions.iter()
+        .find(|region| region.states.get(name).is_some())
+        .map(|region| region.states.cfg(name))
+        .unwrap_or_default()
+}
+
+/// Indices of items which only exist if their state is compiled, along with the number of
+/// items. Items of states which are not compiled take up no index.
+fn conditional_indices(compiled: &[Option<TokenStream>]) -> (Vec<TokenStream>, TokenStream) {
+    let index = |compiled: &[Option<TokenStream>]| {
+        let unconditional = compiled.iter().filter(|compiled| compiled.is_none()).count();
+        let conditional = compiled.iter().flatten();
+
+        quote! { #unconditional #(+ (#conditional as usize))* }
+    };
+
+    let indices = (0..compiled.len())
+        .map(|position| index(&compiled[..position]))
+        .collect();
+
+    (indices, index(compiled))
+}
+
+/// Names of all methods generated on `Machine` besides the event methods themselves
+pub fn method_names(machine: &Machine) -> Vec<String> {
+    let mut names = [
+        "new", "context", "context_mut", "snapshot", "restore", "to_dot", "to_mermaid",
+        "to_plantuml", "transition", "resume_history", "process_event", "dispatch",
+        "run_to_completion", "run_enter_actions", "run_enter_action", "run_exit_action",
+    ].iter()
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>();
+
+    if machine.typestate.is_some() {
+        names.extend(["into_inner", "cast"].iter().map(|name| name.to_string()));
+    }
+
+    for region in machine.regions.iter() {
+        let prefix = region.name.as_ref()
+            .map(|name| format!("{}_", name))
+            .unwrap_or_default();
+
+        names.push(format!("{}state", prefix));
+        names.push(format!("{}state_mut", prefix));
+        names.push(format!("{}state_identifier", prefix));
+    }
+
+    for state in machine.regions.iter().flat_map(|region| region.states.states.iter()) {
+        names.push(format!("is_{}", state.name.to_string().to_lowercase()));
+    }
+
+    for action in machine.enter_actions.iter() {
+        names.push(format!("on_enter_{}", action.state.to_string().to_lowercase()));
+    }
+
+    for action in machine.exit_actions.iter() {
+        names.push(format!("on_exit_{}", action.state.to_string().to_lowercase()));
+    }
+
+    for event in machine.events.iter() {
+        names.push(format!("handle_{}", event.name));
+        names.push(format!("process_{}", event.name));
+        names.extend(
+            (0..machine.regions.len()).map(|index| format!("fire_{}_{}", event.name, index)),
+        );
+    }
+
+    names
+}
+
+fn variant_with_docs(machine: &Machine, region: &Region, state: &State) -> TokenStream {
+    let docs = diagram::describe_state(machine, region, &state.name);
+    let attributes = &state.attributes;
+    let variant = &state.variant;
+
+    quote! {
+        #(#attributes)*
+        #[doc = ""]
+        #(#[doc = #docs])*
+        #variant
+    }
+}
+
+fn prepare(machine: Machine) -> Prepared {
     // The ordering of the vectors here is crucial as the nth item of a state_ prefixed
     // vector corresponds to the nth item of another state_ prefixed vector!
     // This holds for all vectors with the same prefix!
 
-    let (state_idents, state_defaults): (Vec<_>, Vec<_>) = machine.states.defaults.iter()
-        .map(|(ident, default)| (ident.clone(), default.clone()))
+    let states = machine.regions.iter()
+        .flat_map(|region| region.states.states.iter().map(move |state| (region, state)))
+        .collect::<Vec<_>>();
+
+    let state_variants = states.iter()
+        .filter(|(_, state)| state.parent.is_none())
+        .map(|(region, state)| variant_with_docs(&machine, region, state))
+        .collect::<Vec<_>>();
+    let state_idents = states.iter()
+        .map(|(_, state)| state.name.clone())
+        .collect::<Vec<_>>();
+    let state_parents = states.iter()
+        .map(|(_, state)| match state.parent.clone() {
+            Some(parent) => quote! { core::option::Option::Some(StateIdentifier::#parent) },
+            None => quote! { core::option::Option::None },
+        })
+        .collect::<Vec<_>>();
+    let state_paths = states.iter()
+        .map(|(region, state)| {
+            region.states.path(&state.name).iter()
+                .map(|state| state.to_string())
+                .collect::<Vec<_>>()
+                .join("::")
+        })
+        .collect::<Vec<_>>();
+    let state_predicates = state_idents.iter()
+        .map(|name| Ident::new(&format!("is_{}", name.to_string().to_lowercase()), name.span()))
+        .collect::<Vec<_>>();
+    let state_initials = states.iter()
+        .map(|(region, state)| region.states.initial_leaf(&state.name))
+        .collect::<Vec<_>>();
+    let state_cfgs = states.iter()
+        .map(|(region, state)| region.states.cfg(&state.name))
+        .collect::<Vec<_>>();
+
+    let region_initials = machine.regions.iter()
+        .map(|region| region.states.initial_leaf(&region.states.initial))
+        .collect::<Vec<_>>();
+    let region_accessors = machine.regions.iter()
+        .enumerate()
+        .map(|(index, region)| {
+            let prefix = region.name.as_ref()
+                .map(|name| format!("{}_", name))
+                .unwrap_or_default();
+            let span = region.name.as_ref()
+                .map(|name| name.span())
+                .unwrap_or_else(Span::call_site);
+            let state_identifier = Ident::new(&format!("{}state_identifier", prefix), span);
+            let state = Ident::new(&format!("{}state", prefix), span);
+            let state_mut = Ident::new(&format!("{}state_mut", prefix), span);
+
+            quote! {
+                pub fn #state_identifier(&self) -> StateIdentifier {
+                    self.current_states[#index]
+                }
+
+                pub fn #state(&self) -> &State {
+                    &self.states[self.current_states[#index].index()]
+                }
+
+                pub fn #state_mut(&mut self) -> &mut State {
+                    &mut self.states[self.current_states[#index].index()]
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let (composite_names, composite_variants): (Vec<_>, Vec<_>) = states.iter()
+        .filter(|(_, state)| state.is_composite())
+        .map(|(region, state)| {
+            let variants = state.children.iter()
+                .filter_map(|child| region.states.get(child))
+                .map(|child| variant_with_docs(&machine, region, child));
+
+            (state.name.clone(), quote! { #(#variants,)* })
+        })
         .unzip();
-    let state_initial = machine.states.initial.clone();
-    let state_definitions = machine.states.definition.clone();
-    let state_names = state_idents
-        .iter()
-        .map(|name| Ident::new(name.to_string().to_lowercase().as_str(), name.span()))
+    let composites = states.iter()
+        .filter(|(_, state)| state.is_composite())
+        .collect::<Vec<_>>();
+    let (composite_indices, composite_count) = conditional_indices(
+        &composites.iter()
+            .map(|(region, state)| region.states.is_compiled(&state.name))
+            .collect::<Vec<_>>(),
+    );
+    let composite_initials = composites.iter()
+        .map(|(region, state)| region.states.initial_leaf(&state.name))
+        .collect::<Vec<_>>();
+    let composite_cfgs = composites.iter()
+        .map(|(region, state)| region.states.cfg(&state.name))
+        .collect::<Vec<_>>();
+
+    let leaves = states.iter()
+        .filter(|(_, state)| !state.is_composite())
+        .collect::<Vec<_>>();
+    let leaf_idents = leaves.iter()
+        .map(|(_, state)| state.name.clone())
+        .collect::<Vec<_>>();
+    let (leaf_indices, leaf_count) = conditional_indices(
+        &leaves.iter()
+            .map(|(region, state)| region.states.is_compiled(&state.name))
+            .collect::<Vec<_>>(),
+    );
+    let leaf_defaults = leaves.iter()
+        .map(|(_, state)| state.default.clone())
+        .collect::<Vec<_>>();
+    let leaf_cfgs = leaves.iter()
+        .map(|(region, state)| region.states.cfg(&state.name))
+        .collect::<Vec<_>>();
+    let leaf_patterns = machine.regions.iter()
+        .flat_map(|region| {
+            region.states.leaves().into_iter().map(move |state| {
+                let path = region.states.path(&state.name);
+                let mut pattern = TokenStream::new();
+
+                // matches the data of the leaf state wrapped into all of its parent states
+                for (index, name) in path.iter().enumerate().rev() {
+                    let enum_name = match index {
+                        0 => Ident::new("State", Span::call_site()),
+                        _ => path[index - 1].clone(),
+                    };
+
+                    pattern = match pattern.is_empty() {
+                        true => quote! { #enum_name::#name { .. } },
+                        false => quote! { #enum_name::#name(#pattern) },
+                    };
+                }
+
+                pattern
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let region_indices = (0..machine.regions.len()).collect::<Vec<_>>();
+    let region_leaves = machine.regions.iter()
+        .map(|region| {
+            let leaves = region.states.leaves().into_iter()
+                .map(|state| {
+                    let name = &state.name;
+                    let cfg = region.states.cfg(name);
+                    quote! { #cfg StateIdentifier::#name => {}, }
+                });
+
+            quote! { #(#leaves)* }
+        })
         .collect::<Vec<_>>();
 
+    // without the standard library the error types only implement `Display`
+    let (std_error_transition, std_error_restore) = if cfg!(feature = "std") {
+        (
+            quote! { impl std::error::Error for TransitionError {} },
+            quote! { impl std::error::Error for RestoreError {} },
+        )
+    } else {
+        (quote! {}, quote! {})
+    };
+
+    let serde_derive = if cfg!(feature = "serde") {
+        quote! { #[derive(serde::Serialize, serde::Deserialize)] }
+    } else {
+        quote! {}
+    };
+
+    let (context_type, context_param, context_value) = match machine.context.clone() {
+        Some(ty) => (quote! { #ty }, quote! { context: Context }, quote! { context }),
+        None => (quote! { () }, quote! {}, quote! { () }),
+    };
+
+    let enter_actions = prepare_actions(&machine, &machine.enter_actions, "enter");
+    let exit_actions = prepare_actions(&machine, &machine.exit_actions, "exit");
+
     let handler_names = machine.events.iter()
-        .map(|event| Ident::new(&format!("handle_{}", event.name.to_string()), event.name.span()))
+        .map(|event| Ident::new(&format!("handle_{}", event.name), event.name.span()))
         .collect::<Vec<_>>();
     let handler_old_param_names = machine.events.iter()
         .map(|event| event.old_param_name.clone())
@@ -45,6 +389,25 @@ fn prepare<'a>(machine: Machine) -> Prepared {
     let handler_new_param_names = machine.events.iter()
         .map(|event| event.new_param_name.clone())
         .collect::<Vec<_>>();
+    let handler_context_param_names = machine.events.iter()
+        .map(|event| event.context_param_name.clone()
+            .unwrap_or_else(|| Ident::new("_context", event.name.span())))
+        .collect::<Vec<_>>();
+    let handler_queue_param_names = machine.events.iter()
+        .map(|event| event.queue_param_name.clone()
+            .unwrap_or_else(|| Ident::new("_queue", event.name.span())))
+        .collect::<Vec<_>>();
+    let handler_payloads = machine.events.iter()
+        .map(|event| {
+            let (names, types): (Vec<_>, Vec<_>) = event.payload.iter()
+                .map(|param| (param.name.clone(), param.ty.clone()))
+                .unzip();
+
+            quote! {
+                #(#names: #types,)*
+            }
+        })
+        .collect::<Vec<_>>();
     let handler_bodies = machine.events.iter()
         .map(|event| event.body.clone())
         .collect::<Vec<_>>();
@@ -52,125 +415,1062 @@ fn prepare<'a>(machine: Machine) -> Prepared {
     let event_names = machine.events.iter()
         .map(|event| event.name.clone())
         .collect::<Vec<_>>();
-    let event_handlers = handler_names.clone();
-    let event_transitions = event_names.iter()
-        .map(|name| {
-            let mut from_identifiers = Vec::new();
-            let mut from_names = Vec::new();
-            let mut to_identifiers = Vec::new();
-            let mut to_names = Vec::new();
-
-            if let Some(trns) = machine.transitions.get(name) {
-                from_identifiers = trns.iter()
-                    .map(|transition| transition.from.clone())
-                    .collect::<Vec<_>>();
-                from_names = from_identifiers.iter()
-                    .map(|from| Ident::new(from.to_string().to_lowercase().as_str(), from.span()))
-                    .collect::<Vec<_>>();
-                to_identifiers = trns.iter()
-                    .map(|transition| transition.to.clone())
-                    .collect::<Vec<_>>();
-                to_names = to_identifiers.iter()
-                    .map(|to| Ident::new(to.to_string().to_lowercase().as_str(), to.span()))
-                    .collect::<Vec<_>>();
-            }
-
-            return quote! {
-                let (from, to, ident) = match self.current_state {
+    let event_dispatchable = machine.events.iter()
+        .map(|event| event.is_dispatchable())
+        .collect::<Vec<_>>();
+    let event_strings = event_names.iter()
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>();
+    let event_variants = machine.events.iter()
+        .map(|event| event.variant_name())
+        .collect::<Vec<_>>();
+    let event_fields = machine.events.iter()
+        .map(|event| {
+            let (names, types): (Vec<_>, Vec<_>) = event.payload.iter()
+                .map(|param| (param.name.clone(), param.ty.clone()))
+                .unzip();
+
+            match event.payload.is_empty() {
+                true => quote! {},
+                false => quote! { { #(#names: #types,)* } },
+            }
+        })
+        .collect::<Vec<_>>();
+    let event_patterns = machine.events.iter()
+        .map(|event| {
+            let names = event.payload.iter()
+                .map(|param| param.name.clone());
+
+            match event.payload.is_empty() {
+                true => quote! {},
+                false => quote! { { #(#names,)* } },
+            }
+        })
+        .collect::<Vec<_>>();
+    let event_arguments = machine.events.iter()
+        .map(|event| {
+            let names = event.payload.iter()
+                .map(|param| param.name.clone());
+
+            quote! { #(#names,)* }
+        })
+        .collect::<Vec<_>>();
+
+    let event_docs = event_names.iter()
+        .map(|name| diagram::describe_event(&machine, name))
+        .collect::<Vec<_>>();
+    let event_cfgs = machine.events.iter()
+        .map(|event| event.cfg_attributes())
+        .collect::<Vec<_>>();
+    let event_method_attributes = machine.events.iter()
+        .map(|event| event.method_attributes())
+        .collect::<Vec<_>>();
+    let event_variant_attributes = machine.events.iter()
+        .map(|event| event.variant_attributes())
+        .collect::<Vec<_>>();
+    let event_processors = event_names.iter()
+        .map(|name| Ident::new(&format!("process_{}", name), name.span()))
+        .collect::<Vec<_>>();
+
+    let event_transitions = machine.events.iter()
+        .zip(handler_names.iter())
+        .zip(event_processors.iter())
+        .map(|((event, handler), process)| {
+            let name = &event.name;
+            let cfg = event.cfg_attributes();
+            let event_string = name.to_string();
+            let arguments = event.payload.iter()
+                .map(|param| param.name.clone());
+            let payload = event.payload.iter()
+                .map(|param| {
+                    let (name, ty) = (&param.name, &param.ty);
+                    quote! { #name: #ty, }
+                })
+                .collect::<Vec<_>>();
+
+            // the handler receives the states of a single transition, so every region runs it
+            // for its own transition
+            let fire_fns = machine.regions.iter()
+                .enumerate()
+                .map(|(index, region)| {
+                    let states = &region.states;
+                    let fire = Ident::new(&format!("fire_{}_{}", name, index), name.span());
+                    let trns = region.transitions.get(name)
+                        .map(|trns| trns.as_slice())
+                        .unwrap_or_default();
+
+                    let arms = by_precedence(trns).into_iter()
+                        .filter_map(|(source, transition)| {
+                            let from_identifiers = source.leaves(states);
+                            if from_identifiers.is_empty() {
+                                return None;
+                            }
+
+                            let to = &transition.to;
+                            let to_identifier = states.initial_leaf(to);
+                            let target = match transition.history {
+                                Some(History::Shallow) => quote! { self.resume_history(StateIdentifier::#to, false) },
+                                Some(History::Deep) => quote! { self.resume_history(StateIdentifier::#to, true) },
+                                None => quote! { StateIdentifier::#to_identifier },
+                            };
+                            let guard = transition.guard.clone()
+                                .map(|guard| quote! { if #guard });
+                            let to_cfg = states.cfg(to);
+
+                            // one arm per source, as each of them may be conditionally compiled
+                            let arms = from_identifiers.iter()
+                                .map(|from| {
+                                    let from_cfg = states.cfg(from);
+                                    quote! {
+                                        #from_cfg #to_cfg StateIdentifier::#from #guard => #target,
+                                    }
+                                });
+
+                            Some(quote! { #(#arms)* })
+                        })
+                        .collect::<Vec<_>>();
+
+                    let mut guarded = trns.iter()
+                        .filter(|transition| transition.guard.is_some())
+                        .flat_map(|transition| transition.from.leaves(states))
+                        .collect::<Vec<_>>();
+                    guarded.sort();
+                    guarded.dedup();
+
+                    // all remaining transitions from these states were rejected by their guards
+                    let guard_rejected = guarded.iter()
+                        .map(|from| {
+                            let cfg = states.cfg(from);
+                            quote! {
+                                #cfg StateIdentifier::#from => {
+                                    return core::result::Result::Err(TransitionError {
+                                        event: #event_string,
+                                        state: __dsm_source,
+                                        kind: TransitionErrorKind::GuardRejected,
+                                    });
+                                },
+                            }
+                        })
+                        .collect::<Vec<_>>();
+                    let arguments = arguments.clone();
+                    let payload = payload.clone();
+
+                    (fire.clone(), region.transitions.contains_key(name), quote! {
+                        #cfg
+                        #[allow(unreachable_code, unreachable_patterns)]
+                        fn #fire(
+                            &mut self,
+                            #(#payload)*
+                        ) -> core::result::Result<(), TransitionError> {
+                            let __dsm_source = self.current_states[#index];
+                            let __dsm_target = match __dsm_source {
+                                #(#arms)*
+                                #(#guard_rejected)*
+                                _ => return core::result::Result::Err(TransitionError {
+                                    event: #event_string,
+                                    state: __dsm_source,
+                                    kind: TransitionErrorKind::NoTransition,
+                                }),
+                            };
+
+                            let mut __dsm_snapshot;
+                            let (__dsm_from, __dsm_to) = if __dsm_source == __dsm_target {
+                                __dsm_snapshot = self.states[__dsm_source.index()].clone();
+                                (&mut __dsm_snapshot, &mut self.states[__dsm_target.index()])
+                            } else {
+                                split_states(
+                                    &mut self.states,
+                                    __dsm_source.index(),
+                                    __dsm_target.index(),
+                                )
+                            };
+
+                            let __dsm_checkpoint = self.queue.checkpoint();
+                            let __dsm_outcome = Self::#handler(
+                                __dsm_from,
+                                __dsm_to,
+                                &mut self.context,
+                                &mut self.queue,
+                                #(#arguments,)*
+                            );
+                            if !__dsm_outcome.is_accepted() {
+                                // events raised by a vetoed handler are discarded
+                                self.queue.rollback(__dsm_checkpoint);
+                                return core::result::Result::Err(TransitionError {
+                                    event: #event_string,
+                                    state: __dsm_source,
+                                    kind: TransitionErrorKind::HandlerVetoed,
+                                });
+                            }
+                            self.transition(#index, __dsm_target);
+
+                            core::result::Result::Ok(())
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            // an event without any transitions fails in the first region
+            let mut fired = fire_fns.iter()
+                .filter(|(_, has_transitions, _)| *has_transitions)
+                .map(|(fire, _, _)| fire.clone())
+                .collect::<Vec<_>>();
+            if fired.is_empty() {
+                fired.push(fire_fns[0].0.clone());
+            }
+
+            // the payload is moved into the last region and cloned for all others
+            let calls = fired.iter()
+                .enumerate()
+                .map(|(index, fire)| {
+                    let arguments = event.payload.iter()
+                        .map(|param| {
+                            let name = &param.name;
+                            if index + 1 == fired.len() {
+                                quote! { #name }
+                            } else {
+                                quote! { core::clone::Clone::clone(&#name) }
+                            }
+                        });
+
+                    quote! {
+                        self.#fire(#(#arguments,)*)
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            let fire_fns = fire_fns.into_iter()
+                .map(|(_, _, tokens)| tokens);
+
+            quote! {
+                #(#fire_fns)*
+
+                #cfg
+                fn #process(
+                    &mut self,
+                    #(#payload)*
+                ) -> core::result::Result<(), TransitionError> {
+                    let mut __dsm_fired = false;
+                    let mut __dsm_error = core::option::Option::<TransitionError>::None;
+
                     #(
-                        StateIdentifier::#from_identifiers => (
-                            &mut self.#from_names,
-                            &mut self.#to_names,
-                            StateIdentifier::#to_identifiers,
-                        ),
+                        match #calls {
+                            core::result::Result::Ok(()) => __dsm_fired = true,
+                            core::result::Result::Err(__dsm_err) => {
+                                let replace = __dsm_error
+                                    .map(|error| error.kind < __dsm_err.kind)
+                                    .unwrap_or(true);
+                                if replace {
+                                    __dsm_error = core::option::Option::Some(__dsm_err);
+                                }
+                            },
+                        }
                     )*
-                    _ => return false,
-                };
-            };
+
+                    match __dsm_error {
+                        core::option::Option::Some(error) if !__dsm_fired => {
+                            core::result::Result::Err(error)
+                        },
+                        _ => core::result::Result::Ok(()),
+                    }
+                }
+            }
         })
         .collect::<Vec<_>>();
 
     Prepared {
-        name: machine.name,
+        name: machine.name.clone(),
+        state_variants,
         state_idents,
-        state_defaults,
-        state_initial,
-        state_definitions,
-        state_names,
+        state_parents,
+        state_paths,
+        state_predicates,
+        state_initials,
+        state_cfgs,
+        region_initials,
+        region_accessors,
+        composite_names,
+        composite_variants,
+        composite_indices,
+        composite_initials,
+        composite_cfgs,
+        composite_count,
+        leaf_idents,
+        leaf_indices,
+        leaf_defaults,
+        leaf_patterns,
+        leaf_cfgs,
+        leaf_count,
+        region_indices,
+        region_leaves,
+        serde_derive,
+        std_error_transition,
+        std_error_restore,
+        context_type,
+        context_param,
+        context_value,
+        max_depth: machine.max_depth,
+        enter_actions,
+        exit_actions,
         handler_names,
         handler_old_param_names,
         handler_new_param_names,
+        handler_context_param_names,
+        handler_queue_param_names,
+        handler_payloads,
         handler_bodies,
         event_names,
-        event_handlers,
+        event_dispatchable,
+        event_strings,
+        event_variants,
+        event_fields,
+        event_patterns,
+        event_arguments,
+        event_processors,
+        event_docs,
+        event_cfgs,
+        event_method_attributes,
+        event_variant_attributes,
         event_transitions,
     }
 }
 
+/// The elements of `items` which belong to events that can be stored in the `Event` enum
+fn dispatchable<T: Clone>(items: &[T], dispatchable: &[bool]) -> Vec<T> {
+    items.iter()
+        .zip(dispatchable.iter())
+        .filter(|(_, dispatchable)| **dispatchable)
+        .map(|(item, _)| item.clone())
+        .collect()
+}
+
 pub fn generate(machine: Machine) -> TokenStream {
+    let typestate = typestate::generate_typestate(&machine);
+    let machine_attributes = machine.attributes.clone();
+    let dot = diagram::dot(&machine);
+    let mermaid = diagram::mermaid(&machine);
+    let module_doc = format!(
+        "State machine `{}` generated by `state_machine!`.\n\n```mermaid\n{}```",
+        machine.name,
+        mermaid,
+    );
+    let mut machine_doc = format!(
+        "The `{}` state machine, starting in {}.",
+        machine.name,
+        machine.regions.iter()
+            .map(|region| format!("`{}`", region.states.initial_leaf(&region.states.initial)))
+            .collect::<Vec<_>>()
+            .join(", "),
+    );
+    if !machine.events.is_empty() {
+        machine_doc.push_str("\n\nTransitions:\n");
+        for event in machine.events.iter() {
+            machine_doc.push_str(&format!("\n- {}", diagram::describe_event(&machine, &event.name)));
+        }
+    }
+    let plantuml = diagram::plantuml(&machine);
     let Prepared {
         name,
+        state_variants,
         state_idents,
-        state_defaults,
-        state_initial,
-        state_definitions,
-        state_names,
+        state_parents,
+        state_paths,
+        state_predicates,
+        state_initials,
+        state_cfgs,
+        region_initials,
+        region_accessors,
+        composite_names,
+        composite_variants,
+        composite_indices,
+        composite_initials,
+        composite_cfgs,
+        composite_count,
+        leaf_idents,
+        leaf_indices,
+        leaf_defaults,
+        leaf_patterns,
+        leaf_cfgs,
+        leaf_count,
+        region_indices,
+        region_leaves,
+        serde_derive,
+        std_error_transition,
+        std_error_restore,
+        context_type,
+        context_param,
+        context_value,
+        max_depth,
+        enter_actions,
+        exit_actions,
         handler_names,
         handler_old_param_names,
         handler_new_param_names,
+        handler_context_param_names,
+        handler_queue_param_names,
+        handler_payloads,
         handler_bodies,
         event_names,
-        event_handlers,
+        event_dispatchable,
+        event_strings,
+        event_variants,
+        event_fields,
+        event_patterns,
+        event_arguments,
+        event_processors,
+        event_docs,
+        event_cfgs,
+        event_method_attributes,
+        event_variant_attributes,
         event_transitions,
     } = prepare(machine);
-    let state_names2 = state_names.clone();
+    let state_idents = &state_idents;
+    let state_cfgs = &state_cfgs;
+    let state_paths = &state_paths;
+    let region_count = region_initials.len();
+    let composite_names = &composite_names;
+    let composite_cfgs = &composite_cfgs;
+    let handler_payloads = &handler_payloads;
+    let event_cfgs = &event_cfgs;
+    let event_processors = &event_processors;
+    let event_arguments = &event_arguments;
+
+    // events with borrowed payloads cannot be stored in `Event`, they are only processed by
+    // their event methods
+    let enum_strings = &dispatchable(&event_strings, &event_dispatchable);
+    let enum_variants = &dispatchable(&event_variants, &event_dispatchable);
+    let enum_fields = dispatchable(&event_fields, &event_dispatchable);
+    let enum_patterns = dispatchable(&event_patterns, &event_dispatchable);
+    let enum_arguments = dispatchable(event_arguments, &event_dispatchable);
+    let enum_processors = dispatchable(event_processors, &event_dispatchable);
+    let enum_docs = dispatchable(&event_docs, &event_dispatchable);
+    let enum_cfgs = &dispatchable(event_cfgs, &event_dispatchable);
+    let enum_attributes = dispatchable(&event_variant_attributes, &event_dispatchable);
+    let events_without_payload = enum_strings.iter()
+        .zip(enum_variants.iter())
+        .zip(enum_cfgs.iter())
+        .zip(enum_fields.iter())
+        .filter(|(_, fields)| fields.is_empty())
+        .map(|(((string, variant), cfg), _)| (string.clone(), variant.clone(), cfg.clone()))
+        .collect::<Vec<_>>();
+    let event_strings_without_payload = events_without_payload.iter()
+        .map(|(string, _, _)| string);
+    let event_variants_without_payload = events_without_payload.iter()
+        .map(|(_, variant, _)| variant);
+    let event_cfgs_without_payload = events_without_payload.iter()
+        .map(|(_, _, cfg)| cfg);
+    let composite_names2 = composite_names.clone();
+    let leaf_idents = &leaf_idents;
+    let leaf_cfgs = &leaf_cfgs;
+    let serde_derive = &serde_derive;
+    let composite_serde_derives = composite_names.iter()
+        .map(|_| serde_derive);
+
+    let attributes = &machine_attributes;
 
     quote! {
+        #(#attributes)*
+        #[doc = ""]
+        #[doc = #module_doc]
         mod #name {
+            #[allow(unused_imports)]
+            use super::*;
+
             #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+            #serde_derive
             pub enum State {
-                #state_definitions
+                #(#state_variants,)*
+            }
+
+            #[allow(deprecated)]
+            impl State {
+                pub fn identifier(&self) -> StateIdentifier {
+                    match self {
+                        #(#leaf_cfgs #leaf_patterns => StateIdentifier::#leaf_idents,)*
+                    }
+                }
+            }
+
+            #(
+                #composite_cfgs
+                #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+                #composite_serde_derives
+                pub enum #composite_names {
+                    #composite_variants
+                }
+            )*
+
+            #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+            #serde_derive
+            pub enum StateIdentifier {
+                #(#state_cfgs #state_idents,)*
+            }
+
+            impl StateIdentifier {
+                pub fn parent(self) -> core::option::Option<StateIdentifier> {
+                    match self {
+                        #(#state_cfgs StateIdentifier::#state_idents => #state_parents,)*
+                    }
+                }
+
+                pub fn contains(self, other: StateIdentifier) -> bool {
+                    let mut current = core::option::Option::Some(other);
+
+                    while let core::option::Option::Some(ident) = current {
+                        if ident == self {
+                            return true;
+                        }
+                        current = ident.parent();
+                    }
+
+                    false
+                }
+
+                fn initial(self) -> StateIdentifier {
+                    match self {
+                        #(#state_cfgs StateIdentifier::#state_idents => StateIdentifier::#state_initials,)*
+                    }
+                }
+
+                #[allow(dead_code, unreachable_patterns)]
+                fn history_index(self) -> usize {
+                    match self {
+                        #(#composite_cfgs StateIdentifier::#composite_names => #composite_indices,)*
+                        _ => unreachable!("leaf states have no history"),
+                    }
+                }
+
+                #[allow(unreachable_patterns)]
+                fn is_leaf(self) -> bool {
+                    match self {
+                        #(#leaf_cfgs StateIdentifier::#leaf_idents => true,)*
+                        _ => false,
+                    }
+                }
+
+                #[allow(unreachable_patterns)]
+                fn index(self) -> usize {
+                    match self {
+                        #(#leaf_cfgs StateIdentifier::#leaf_idents => #leaf_indices,)*
+                        _ => unreachable!("composite states carry no state data"),
+                    }
+                }
+            }
+
+            impl core::fmt::Display for StateIdentifier {
+                fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                    match self {
+                        #(#state_cfgs StateIdentifier::#state_idents => f.write_str(#state_paths),)*
+                    }
+                }
+            }
+
+            #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+            pub struct ParseStateIdentifierError;
+
+            impl core::fmt::Display for ParseStateIdentifierError {
+                fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                    f.write_str("unknown state identifier")
+                }
+            }
+
+            impl core::str::FromStr for StateIdentifier {
+                type Err = ParseStateIdentifierError;
+
+                fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
+                    match s {
+                        #(
+                            #state_cfgs
+                            #state_paths => core::result::Result::Ok(StateIdentifier::#state_idents),
+                        )*
+                        _ => core::result::Result::Err(ParseStateIdentifierError),
+                    }
+                }
+            }
+
+            pub type Context = #context_type;
+
+            /// Graphviz DOT representation of this machine
+            pub const DOT: &str = #dot;
+
+            /// Mermaid `stateDiagram-v2` representation of this machine
+            pub const MERMAID: &str = #mermaid;
+
+            /// PlantUML representation of this machine
+            pub const PLANTUML: &str = #plantuml;
+
+            #serde_derive
+            pub enum Event {
+                #(
+                    #enum_cfgs
+                    #enum_attributes
+                    #[doc = ""]
+                    #[doc = #enum_docs]
+                    #enum_variants #enum_fields,
+                )*
+            }
+
+            #[allow(deprecated)]
+            impl Event {
+                pub fn name(&self) -> &'static str {
+                    match *self {
+                        #(#enum_cfgs Event::#enum_variants { .. } => #enum_strings,)*
+                    }
+                }
+            }
+
+            #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+            pub struct ParseEventError;
+
+            impl core::fmt::Display for ParseEventError {
+                fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                    f.write_str("unknown event or event requires a payload")
+                }
+            }
+
+            #[allow(deprecated)]
+            impl core::str::FromStr for Event {
+                type Err = ParseEventError;
+
+                fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
+                    match s {
+                        #(
+                            #event_cfgs_without_payload
+                            #event_strings_without_payload => {
+                                core::result::Result::Ok(Event::#event_variants_without_payload)
+                            },
+                        )*
+                        _ => core::result::Result::Err(ParseEventError),
+                    }
+                }
             }
 
-            #[allow(dead_code)]
-            enum StateIdentifier {
-                #(#state_idents,)*
+            const MAX_DEPTH: usize = #max_depth;
+
+            pub struct Queue {
+                events: [core::option::Option<Event>; MAX_DEPTH],
+                head: usize,
+                len: usize,
+                overflowed: bool,
             }
 
+            impl Queue {
+                fn new() -> Self {
+                    const EMPTY: core::option::Option<Event> = core::option::Option::None;
+
+                    Self {
+                        events: [EMPTY; MAX_DEPTH],
+                        head: 0,
+                        len: 0,
+                        overflowed: false,
+                    }
+                }
+
+                /// Queues `event` to be dispatched after the current transition completed
+                pub fn raise(&mut self, event: Event) {
+                    if self.len == MAX_DEPTH {
+                        self.overflowed = true;
+                        return;
+                    }
+
+                    let index = (self.head + self.len) % MAX_DEPTH;
+                    self.events[index] = core::option::Option::Some(event);
+                    self.len += 1;
+                }
+
+                pub fn len(&self) -> usize {
+                    self.len
+                }
+
+                pub fn is_empty(&self) -> bool {
+                    self.len == 0
+                }
+
+                fn pop(&mut self) -> core::option::Option<Event> {
+                    if self.len == 0 {
+                        return core::option::Option::None;
+                    }
+
+                    let event = self.events[self.head].take();
+                    self.head = (self.head + 1) % MAX_DEPTH;
+                    self.len -= 1;
+
+                    event
+                }
+
+                /// The state of the queue before an event handler runs
+                fn checkpoint(&self) -> (usize, bool) {
+                    (self.len, self.overflowed)
+                }
+
+                /// Discards all events raised since `checkpoint`, including an overflow
+                fn rollback(&mut self, (len, overflowed): (usize, bool)) {
+                    while self.len > len {
+                        self.len -= 1;
+                        let index = (self.head + self.len) % MAX_DEPTH;
+                        self.events[index] = core::option::Option::None;
+                    }
+
+                    self.overflowed = overflowed;
+                }
+
+                fn clear(&mut self) {
+                    self.rollback((0, false));
+                    self.head = 0;
+                }
+            }
+
+            #[doc = #machine_doc]
             pub struct Machine {
-                current_state: StateIdentifier,
-                #(#state_names: State,)*
+                current_states: [StateIdentifier; #region_count],
+                context: Context,
+                queue: Queue,
+                states: [State; #leaf_count],
+                history: [StateIdentifier; #composite_count],
+            }
+
+            #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+            pub enum TransitionErrorKind {
+                NoTransition,
+                GuardRejected,
+                HandlerVetoed,
+                MaxDepthExceeded,
+            }
+
+            impl core::fmt::Display for TransitionErrorKind {
+                fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                    match self {
+                        TransitionErrorKind::NoTransition => f.write_str("no transition defined"),
+                        TransitionErrorKind::GuardRejected => f.write_str("rejected by guard"),
+                        TransitionErrorKind::HandlerVetoed => f.write_str("vetoed by event handler"),
+                        TransitionErrorKind::MaxDepthExceeded => f.write_str("maximum event queue depth exceeded"),
+                    }
+                }
+            }
+
+            #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+            pub struct TransitionError {
+                pub event: &'static str,
+                pub state: StateIdentifier,
+                pub kind: TransitionErrorKind,
+            }
+
+            impl core::fmt::Display for TransitionError {
+                fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                    write!(f, "event `{}` failed in state `{}`: {}", self.event, self.state, self.kind)
+                }
+            }
+
+            #std_error_transition
+
+            /// The current states, the data of all states and the history of a machine
+            #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+            #serde_derive
+            pub struct Snapshot {
+                pub current_states: [StateIdentifier; #region_count],
+                pub states: [State; #leaf_count],
+                pub history: [StateIdentifier; #composite_count],
+            }
+
+            #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+            pub enum RestoreError {
+                /// The current state of a region is not one of its leaf states
+                InvalidState(StateIdentifier),
+                /// The data of a state belongs to a different state
+                InvalidStateData(StateIdentifier),
+                /// The history of a composite state is not one of its nested leaf states
+                InvalidHistory(StateIdentifier),
+            }
+
+            impl core::fmt::Display for RestoreError {
+                fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                    match self {
+                        RestoreError::InvalidState(state) => {
+                            write!(f, "`{}` is not a valid current state", state)
+                        },
+                        RestoreError::InvalidStateData(state) => {
+                            write!(f, "data of state `{}` belongs to a different state", state)
+                        },
+                        RestoreError::InvalidHistory(state) => {
+                            write!(f, "invalid history of state `{}`", state)
+                        },
+                    }
+                }
+            }
+
+            #std_error_restore
+
+            pub trait HandlerOutcome {
+                fn is_accepted(&self) -> bool;
             }
 
+            impl HandlerOutcome for () {
+                fn is_accepted(&self) -> bool {
+                    true
+                }
+            }
+
+            impl HandlerOutcome for bool {
+                fn is_accepted(&self) -> bool {
+                    *self
+                }
+            }
+
+            impl<T, E> HandlerOutcome for core::result::Result<T, E> {
+                fn is_accepted(&self) -> bool {
+                    self.is_ok()
+                }
+            }
+
+            fn split_states(states: &mut [State], old: usize, new: usize) -> (&mut State, &mut State) {
+                if old < new {
+                    let (head, tail) = states.split_at_mut(new);
+                    (&mut head[old], &mut tail[0])
+                } else {
+                    let (head, tail) = states.split_at_mut(old);
+                    (&mut tail[0], &mut head[new])
+                }
+            }
+
+            #[allow(deprecated, clippy::too_many_arguments)]
             impl Machine {
-                pub fn new() -> Self {
+                pub fn new(#context_param) -> Self {
                     Self {
-                        current_state: StateIdentifier::#state_initial,
-                        #(#state_names2: #state_defaults,)*
+                        current_states: [#(StateIdentifier::#region_initials,)*],
+                        context: #context_value,
+                        queue: Queue::new(),
+                        states: [#(#leaf_cfgs #leaf_defaults,)*],
+                        history: [#(#composite_cfgs StateIdentifier::#composite_initials,)*],
+                    }
+                }
+
+                #(#region_accessors)*
+
+                #(
+                    #state_cfgs
+                    pub fn #state_predicates(&self) -> bool {
+                        self.current_states.iter()
+                            .any(|current| StateIdentifier::#state_idents.contains(*current))
+                    }
+                )*
+
+                pub fn context(&self) -> &Context {
+                    &self.context
+                }
+
+                /// Captures the current states and the data of all states. The context is not
+                /// part of the snapshot.
+                pub fn snapshot(&self) -> Snapshot {
+                    Snapshot {
+                        current_states: self.current_states,
+                        states: self.states.clone(),
+                        history: self.history,
+                    }
+                }
+
+                /// Restores the states of a snapshot without running any entry or exit actions.
+                /// Pending raised events are discarded.
+                #[allow(unreachable_patterns)]
+                pub fn restore(
+                    &mut self,
+                    snapshot: Snapshot,
+                ) -> core::result::Result<(), RestoreError> {
+                    #(
+                        match snapshot.current_states[#region_indices] {
+                            #region_leaves
+                            state => {
+                                return core::result::Result::Err(RestoreError::InvalidState(state));
+                            },
+                        }
+                    )*
+
+                    for (index, state) in snapshot.states.iter().enumerate() {
+                        let identifier = state.identifier();
+                        if identifier.index() != index {
+                            let error = RestoreError::InvalidStateData(identifier);
+                            return core::result::Result::Err(error);
+                        }
+                    }
+
+                    let composites: [StateIdentifier; #composite_count] = [
+                        #(#composite_cfgs StateIdentifier::#composite_names2,)*
+                    ];
+                    for (composite, last) in composites.iter().zip(snapshot.history.iter()) {
+                        if !last.is_leaf() || !composite.contains(*last) {
+                            let error = RestoreError::InvalidHistory(*composite);
+                            return core::result::Result::Err(error);
+                        }
+                    }
+
+                    self.current_states = snapshot.current_states;
+                    self.states = snapshot.states;
+                    self.history = snapshot.history;
+                    self.queue.clear();
+
+                    core::result::Result::Ok(())
+                }
+
+                pub fn to_dot(&self) -> &'static str {
+                    DOT
+                }
+
+                pub fn to_mermaid(&self) -> &'static str {
+                    MERMAID
+                }
+
+                pub fn to_plantuml(&self) -> &'static str {
+                    PLANTUML
+                }
+
+                pub fn context_mut(&mut self) -> &mut Context {
+                    &mut self.context
+                }
+
+                #enter_actions
+
+                #exit_actions
+
+                fn run_enter_actions(
+                    &mut self,
+                    ident: StateIdentifier,
+                    common: core::option::Option<StateIdentifier>,
+                    target: StateIdentifier,
+                ) {
+                    if ident.parent() != common {
+                        self.run_enter_actions(ident.parent().unwrap(), common, target);
                     }
+
+                    Self::run_enter_action(ident, &mut self.states[target.index()], &mut self.context);
+                }
+
+                fn transition(&mut self, region: usize, target: StateIdentifier) {
+                    let source = self.current_states[region];
+
+                    // the innermost state containing source and target is neither left nor
+                    // entered, except on self-transitions which leave and re-enter the state
+                    let mut common = match source == target {
+                        true => source.parent(),
+                        false => core::option::Option::Some(source),
+                    };
+                    while let core::option::Option::Some(ident) = common {
+                        if ident.contains(target) {
+                            break;
+                        }
+                        common = ident.parent();
+                    }
+
+                    let mut current = core::option::Option::Some(source);
+                    while current != common {
+                        let ident = current.unwrap();
+                        Self::run_exit_action(ident, &mut self.states[source.index()], &mut self.context);
+                        current = ident.parent();
+                    }
+
+                    self.run_enter_actions(target, common, target);
+                    self.current_states[region] = target;
+
+                    let mut parent = target.parent();
+                    while let core::option::Option::Some(ident) = parent {
+                        self.history[ident.history_index()] = target;
+                        parent = ident.parent();
+                    }
+                }
+
+                #[allow(dead_code)]
+                fn resume_history(&self, composite: StateIdentifier, deep: bool) -> StateIdentifier {
+                    let mut last = self.history[composite.history_index()];
+
+                    if deep {
+                        return last;
+                    }
+
+                    while last.parent() != core::option::Option::Some(composite) {
+                        last = last.parent().unwrap();
+                    }
+
+                    last.initial()
                 }
 
                 #(
+                    #event_cfgs
                     fn #handler_names(
                         #handler_old_param_names: &mut State,
                         #handler_new_param_names: &mut State,
-                    ) {
+                        #handler_context_param_names: &mut Context,
+                        #handler_queue_param_names: &mut Queue,
+                        #handler_payloads
+                    ) -> impl HandlerOutcome {
                         #handler_bodies
                     }
                 )*
 
+                #(#event_transitions)*
+
                 #(
-                    #[allow(unreachable_code)]
-                    pub fn #event_names(&mut self) -> bool {
-                        #event_transitions
+                    #event_cfgs
+                    #event_method_attributes
+                    #[doc = ""]
+                    #[doc = #event_docs]
+                    pub fn #event_names(
+                        &mut self,
+                        #handler_payloads
+                    ) -> core::result::Result<(), TransitionError> {
+                        let result = self.#event_processors(#event_arguments);
+                        self.run_to_completion(result)
+                    }
+                )*
 
-                        Self::#event_handlers(from, to);
-                        self.current_state = ident;
+                fn process_event(
+                    &mut self,
+                    event: Event,
+                ) -> core::result::Result<(), TransitionError> {
+                    match event {
+                        #(
+                            #enum_cfgs
+                            Event::#enum_variants #enum_patterns => self.#enum_processors(#enum_arguments),
+                        )*
+                    }
+                }
+
+                /// Dispatches `event` and all events raised by event handlers until the machine
+                /// comes to rest. Returns the first error of any of the dispatched events.
+                pub fn dispatch(
+                    &mut self,
+                    event: Event,
+                ) -> core::result::Result<(), TransitionError> {
+                    let result = self.process_event(event);
+                    self.run_to_completion(result)
+                }
+
+                /// Dispatches all raised events after an event resulted in `result`
+                fn run_to_completion(
+                    &mut self,
+                    mut result: core::result::Result<(), TransitionError>,
+                ) -> core::result::Result<(), TransitionError> {
+                    let mut depth = 0;
 
-                        true
+                    while let core::option::Option::Some(event) = self.queue.pop() {
+                        depth += 1;
+                        if depth > MAX_DEPTH || self.queue.overflowed {
+                            self.queue.clear();
+                            return core::result::Result::Err(TransitionError {
+                                event: event.name(),
+                                state: self.current_states[0],
+                                kind: TransitionErrorKind::MaxDepthExceeded,
+                            });
+                        }
+
+                        let outcome = self.process_event(event);
+                        if result.is_ok() {
+                            result = outcome;
+                        }
                     }
-                )*
+
+                    result
+                }
             }
+
+            #typestate
         }
     }
 }
@@ -1,17 +1,25 @@
 #![recursion_limit="512"]
-#![feature(proc_macro_diagnostic)]
+#![cfg_attr(feature = "nightly", feature(proc_macro_diagnostic))]
 
 extern crate proc_macro;
 
+mod diagnostic;
 mod error;
+mod context;
+mod actions;
 mod events;
 mod states;
 mod transitions;
+mod regions;
+mod queue;
 mod syntax;
 mod semantic;
+mod typestate;
+mod diagram;
 mod generate;
 
-use proc_macro::{Diagnostic, Level};
+use proc_macro2::Span;
+use diagnostic::SpanDiagnosticExt;
 use error::StateMachineError;
 
 #[proc_macro]
@@ -21,18 +29,20 @@ pub fn state_machine(machine: proc_macro::TokenStream) -> proc_macro::TokenStrea
 
     match result {
         Err(StateMachineError::NoFurtherTokens) => {
-            Diagnostic::new(Level::Error, "internal parser error, please report to upstream!")
-                .emit();
-
-            proc_macro::TokenStream::new()
+            diagnostic::report(Some(
+                Span::call_site().error("internal parser error, please report to upstream!")
+            )).into()
         },
         Err(StateMachineError::CompilationFailure(diagnostics)) => {
-            diagnostics.emit();
-
-            proc_macro::TokenStream::new()
+            diagnostic::report(Some(diagnostics)).into()
+        },
+        Err(StateMachineError::Emitted) => {
+            diagnostic::report(None).into()
         },
         Ok(machine) => {
-            generate::generate(machine).into()
+            let mut tokens = generate::generate(machine);
+            tokens.extend(diagnostic::report(None));
+            tokens.into()
         }
     }
 }
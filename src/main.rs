@@ -3,17 +3,28 @@ extern crate declarative_state_machine;
 use declarative_state_machine::state_machine;
 
 state_machine! {
+    /// Fills bottles until it is paused or stopped
     machine bottle_filler {
-        event run(_old: &mut State, _new: &mut State) {
+        context: Statistics;
+
+        /// Starts filling bottles
+        event run(_old: &mut State, _new: &mut State, statistics: &mut Context) {
+            statistics.runs += 1;
             println!("Run application");
         }
 
-        event pause(_old: &mut State, _new: &mut State) {
+        event pause(_old: &mut State, new: &mut State, reason: String) {
             //println!("Pause application");
+            *new = State::Paused { reason };
+        }
+
+        on_enter Running {
+            println!("Filling bottles");
         }
 
         states {
             Stopped,
+            /// Waiting for a refill
             Paused{ reason: String },
             Running(String),
         }
@@ -24,7 +35,7 @@ state_machine! {
 
         transitions {
             Stopped => Running : run;
-            Paused  => Running : run;
+            Paused  => Running : run [if battery_ok()];
             Running => Paused  : pause;
             Running => Stopped : stop;
             Paused  => Stopped : stop;
@@ -32,6 +43,22 @@ state_machine! {
     }
 }
 
-fn main() {
-    let _state_machine = bottle_filler::Machine::new();
+pub struct Statistics {
+    runs: usize,
+}
+
+fn battery_ok() -> bool {
+    true
+}
+
+fn main() -> Result<(), bottle_filler::TransitionError> {
+    let mut state_machine = bottle_filler::Machine::new(Statistics { runs: 0 });
+    state_machine.run()?;
+    state_machine.pause("refill".to_string())?;
+    state_machine.run()?;
+    state_machine.dispatch(bottle_filler::Event::Stop)?;
+    println!("Application is {}", state_machine.state_identifier());
+    println!("Application ran {} times", state_machine.context().runs);
+
+    Ok(())
 }
@@ -0,0 +1,53 @@
+use crate::error::StateMachineResult;
+use crate::diagnostic::SpanDiagnosticExt;
+
+use proc_macro2::{TokenTree, Span, Literal};
+
+pub fn parse_max_depth(
+    iter: &mut dyn Iterator<Item = TokenTree>,
+    mut span: Span,
+) -> StateMachineResult<usize> {
+    if let Some(next) = iter.next() {
+        span = next.span();
+        if let TokenTree::Punct(punct) = next {
+            if punct.as_char() == ':' {
+                let depth = iter.next()
+                    .ok_or(span.error("expected maximum event queue depth").into())
+                    .and_then(|next| {
+                        span = next.span();
+                        if let TokenTree::Literal(literal) = next {
+                            return parse_depth(&literal);
+                        }
+
+                        Err(span.error("expected maximum event queue depth").into())
+                    })?;
+
+                if let Some(TokenTree::Punct(punct)) = iter.next() {
+                    if punct.as_char() == ';' {
+                        return Ok(depth);
+                    }
+                }
+
+                return Err(span.error("expected semicolon ';' after max_depth").into());
+            }
+        }
+    }
+
+    Err(span.error("expected queue depth definition 'max_depth: 16;'").into())
+}
+
+fn parse_depth(literal: &Literal) -> StateMachineResult<usize> {
+    let depth = literal.to_string()
+        .trim_end_matches("usize")
+        .replace('_', "")
+        .parse::<usize>()
+        .ok();
+
+    match depth {
+        Some(depth) if depth > 0 => Ok(depth),
+        _ => Err(literal.span()
+            .error("expected a positive integer")
+            .help("the event queue must be able to hold at least one event")
+            .into()),
+    }
+}
@@ -0,0 +1,108 @@
+use crate::error::StateMachineResult;
+use crate::transitions;
+use crate::transitions::Transition;
+use crate::states;
+use crate::states::States;
+use crate::syntax;
+use crate::diagnostic;
+use crate::diagnostic::SpanDiagnosticExt;
+
+use std::collections::HashMap;
+use std::iter::Peekable;
+use proc_macro2::{TokenTree, Span, Ident, Delimiter, token_stream};
+
+pub struct Region {
+    pub name: Option<Ident>,
+    pub states: States,
+    pub transitions: HashMap<Ident, Vec<Transition>>,
+}
+
+impl Region {
+    pub fn new(name: Option<Ident>) -> Self {
+        Self {
+            name,
+            states: States::default(),
+            transitions: HashMap::new(),
+        }
+    }
+
+    /// All transitions grouped by their event, sorted by event name so diagnostics are emitted
+    /// in the same order on every compile
+    pub fn transitions_by_event(&self) -> Vec<(&Ident, &Vec<Transition>)> {
+        let mut transitions = self.transitions.iter().collect::<Vec<_>>();
+        transitions.sort_by_key(|(a, _)| *a);
+        transitions
+    }
+}
+
+fn parse_definition(
+    region: &mut Region,
+    defined: &mut Vec<Ident>,
+    next: TokenTree,
+    iter: &mut Peekable<token_stream::IntoIter>,
+) -> StateMachineResult<()> {
+    let span = next.span();
+    if let TokenTree::Ident(ident) = next {
+        match ident.to_string().as_str() {
+            "states" => {
+                syntax::check_duplicate_definition(&ident, defined)?;
+                region.states = states::parse_states(iter, span)?;
+            },
+            "transitions" => {
+                syntax::check_duplicate_definition(&ident, defined)?;
+                region.transitions = transitions::parse_transitions(iter, span)?;
+            },
+            keyword => {
+                let error = span.error("expected 'states' or 'transitions' keyword");
+
+                return Err(match diagnostic::similar_name(keyword, &["states", "transitions"]) {
+                    Some(similar) => error.help(format!("did you mean `{}`?", similar)),
+                    None => error,
+                }.into());
+            }
+        }
+    } else {
+        return Err(span.error("expected definition of region").into());
+    }
+
+    Ok(())
+}
+
+pub fn parse_region(
+    iter: &mut dyn Iterator<Item = TokenTree>,
+    mut span: Span,
+) -> StateMachineResult<Region> {
+    let mut region = Region::new(None);
+
+    if let Some(next) = iter.next() {
+        span = next.span();
+        if let TokenTree::Ident(ident) = next {
+            region.name = Some(ident);
+        } else {
+            return Err(span.error("expected region name").into());
+        }
+    }
+
+    if let Some(next) = iter.next() {
+        span = next.span();
+        if let TokenTree::Group(group) = next {
+            if let Delimiter::Brace = group.delimiter() {
+                let mut group_iter = group.stream().into_iter().peekable();
+                let mut defined = Vec::new();
+
+                while let Some(next) = group_iter.next() {
+                    let result = parse_definition(&mut region, &mut defined, next, &mut group_iter);
+
+                    if let Err(err) = result {
+                        err.recover()?;
+                        syntax::skip_definition(&mut group_iter, &["states", "transitions"]);
+                    }
+                }
+
+                return Ok(region);
+            }
+        }
+    }
+
+    Err(span.error("expected region body `region name { ... }`").into())
+}
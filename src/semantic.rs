@@ -1,29 +1,440 @@
 use crate::error::StateMachineResult;
+use crate::regions::Region;
+use crate::transitions;
+use crate::transitions::{Source, Transition, History};
 use crate::syntax::Machine;
+use crate::diagnostic;
+use crate::generate;
+use crate::diagnostic::SpanDiagnosticExt;
 
-pub fn parse_semantic(machine: Machine) -> StateMachineResult<Machine> {
+use proc_macro2::{Ident, Span};
+
+fn undefined_state(state: &Ident, states: &[Ident]) {
+    let error = state.span().error("undefined state");
+
+    match diagnostic::similar_name(&state.to_string(), states) {
+        Some(similar) => error.help(format!("did you mean `{}`?", similar).as_str()),
+        None => error.help(format!("add `{}` to the `states` block to resolve", state).as_str()),
+    }.emit();
+}
+
+/// Whether `state`, one of its parents or one of its nested states is part of a transition
+fn has_transitions(region: &Region, state: &Ident) -> bool {
+    region.transitions.values()
+        .flatten()
+        .flat_map(|transition| {
+            let mut states = transition.from.leaves(&region.states);
+            states.push(transition.to.clone());
+            states
+        })
+        .any(|other| {
+            region.states.contains(&other, state) || region.states.contains(state, &other)
+        })
+}
+
+/// The leaf states a transition may enter, given the leaf states reached so far. Deep history
+/// resumes a leaf state which has been reached before, while shallow history enters the initial
+/// leaf state of any direct sub-state which has been reached before.
+fn destinations(region: &Region, transition: &Transition, reachable: &[Ident]) -> Vec<Ident> {
+    let states = &region.states;
+    let mut destinations = vec![states.initial_leaf(&transition.to)];
+
+    let target = states.get(&transition.to);
+
+    if let (Some(History::Shallow), Some(state)) = (transition.history, target) {
+        for child in state.children.iter() {
+            if reachable.iter().any(|leaf| states.contains(child, leaf)) {
+                destinations.push(states.initial_leaf(child));
+            }
+        }
+    }
+
+    destinations
+}
+
+/// The leaf states of `region` which are reachable from its initial state. A guarded transition
+/// may or may not be taken, while an unguarded one shadows all transitions of lower precedence.
+/// As history transitions depend on the states reached before, the search is repeated until
+/// no further states are found.
+fn reachable_leaves(region: &Region) -> Vec<Ident> {
+    let states = &region.states;
+    let transitions = region.transitions.values()
+        .map(|transitions| transitions::by_precedence(transitions))
+        .collect::<Vec<_>>();
+    let mut reachable = vec![states.initial_leaf(&states.initial)];
+
+    loop {
+        let count = reachable.len();
+        let mut index = 0;
+
+        while let Some(leaf) = reachable.get(index).cloned() {
+            index += 1;
+
+            for transitions in transitions.iter() {
+                for (source, transition) in transitions {
+                    if !source.leaves(states).contains(&leaf) {
+                        continue;
+                    }
+
+                    for to in destinations(region, transition, &reachable) {
+                        if !reachable.contains(&to) {
+                            reachable.push(to);
+                        }
+                    }
+
+                    if transition.guard.is_none() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        if reachable.len() == count {
+            return reachable;
+        }
+    }
+}
+
+/// Warns about states which cannot be reached from the initial state and about events which
+/// cannot fire as none of their source states can be reached
+fn check_reachability(machine: &Machine) {
+    let mut fired = Vec::new();
+
+    for region in machine.regions.iter() {
+        let states = &region.states;
+        let reachable = reachable_leaves(region);
+        let is_reachable = |state: &Ident| {
+            reachable.iter().any(|leaf| states.contains(state, leaf))
+        };
+
+        for (event, transitions) in region.transitions.iter() {
+            let fires = transitions.iter()
+                .flat_map(|transition| transition.from.leaves(states))
+                .any(|leaf| reachable.contains(&leaf));
+
+            if fires {
+                fired.push(event.clone());
+            }
+        }
+
+        // states without any transitions have been reported already, and nested states are
+        // only reported if their parent is reachable
+        for state in states.states.iter() {
+            let parent_reachable = state.parent.as_ref().map(is_reachable).unwrap_or(true);
+
+            let is_reported = is_reachable(&state.name)
+                || !parent_reachable
+                || !has_transitions(region, &state.name);
+
+            if !is_reported {
+                state.name.span()
+                    .warning(format!(
+                        "state is unreachable from the initial state `{}`",
+                        states.initial,
+                    ))
+                    .help("add a transition from a reachable state to resolve")
+                    .emit();
+            }
+        }
+    }
+
+    for event in machine.events.iter() {
+        let has_transitions = machine.regions.iter()
+            .any(|region| region.transitions.contains_key(&event.name));
+
+        if has_transitions && !fired.contains(&event.name) {
+            event.name.span()
+                .warning("event can never fire as none of its source states is reachable")
+                .help(
+                    format!(
+                        "add a transition from a reachable state to resolve: `SomeState => OtherState : {}`",
+                        event.name,
+                    ).as_str(),
+                )
+                .emit();
+        }
+    }
+}
+
+/// The span of a single-source transition returned by `transitions::by_precedence`
+fn source_span(source: &Source, transition: &Transition) -> Span {
+    match source {
+        Source::States(paths) => paths[0].last().unwrap().span(),
+        Source::Wildcard(_) => transition.span,
+    }
+}
+
+/// Rejects transitions for the same state and event which can never be taken, as they are
+/// preceded by an unguarded transition, a transition with the same guard or an exact duplicate.
+/// Transitions of nested states and explicit states take precedence over the ones of their
+/// parents and wildcards, so only transitions of the same precedence conflict.
+fn check_determinism(region: &Region) {
+    let states = &region.states;
+
+    for (_, transitions) in region.transitions_by_event() {
+        let arms = transitions::by_precedence(transitions);
+
+        for (index, (source, transition)) in arms.iter().enumerate() {
+            let leaves = source.leaves(states);
+
+            for (other_source, other) in arms[..index].iter() {
+                let same_precedence = match (source, other_source) {
+                    (Source::States(paths), Source::States(other_paths)) => {
+                        paths[0].last() == other_paths[0].last()
+                    },
+                    (Source::Wildcard(_), Source::Wildcard(_)) => {
+                        let other_leaves = other_source.leaves(states);
+                        leaves.iter().all(|leaf| other_leaves.contains(leaf))
+                    },
+                    _ => false,
+                };
+
+                // `A | A => ...` is the same transition
+                if !same_precedence || std::ptr::eq(*transition, *other) {
+                    continue;
+                }
+
+                let guard = transition.guard.as_ref().map(|guard| guard.to_string());
+                let other_guard = other.guard.as_ref().map(|guard| guard.to_string());
+                let span = source_span(source, transition);
+                let other_span = source_span(other_source, other);
+
+                let error = if transition.to_path == other.to_path
+                    && transition.history == other.history
+                    && guard == other_guard
+                {
+                    span.error("duplicate transition")
+                        .help("remove the duplicate transition to resolve")
+                } else if other_guard.is_none() {
+                    span.error(
+                        "transition is never taken as an unguarded transition for the same state \
+                         and event precedes it"
+                    ).help(
+                        "add a guard `[if ...]` to the preceding transition or remove one of them \
+                         to resolve"
+                    )
+                } else if guard == other_guard {
+                    span.error(
+                        "transition is never taken as a preceding transition for the same state \
+                         and event has the same guard"
+                    ).help("make the guards of both transitions disjoint to resolve")
+                } else {
+                    continue;
+                };
+
+                error.span_note(other_span, "preceding transition is defined here").emit();
+                break;
+            }
+        }
+    }
+}
+
+fn parse_region_semantic(region: &Region, machine: &Machine) {
     // check if transitions only contain known states
-    let state_idents = machine.states.defaults.keys()
-        .map(|state| state.clone())
+    let state_idents = region.states.states.iter()
+        .map(|state| state.name.clone())
         .collect::<Vec<_>>();
-    let mut transition_states = machine.transitions.values()
+    let mut referenced_states = region.transitions.values()
         .flat_map(|transitions| {
             transitions.iter().flat_map(|transition| {
-                vec![transition.from.clone(), transition.to.clone()]
+                transition.from.paths().iter()
+                    .map(|path| path.last().unwrap().clone())
+                    .chain(Some(transition.to.clone()))
+                    .collect::<Vec<_>>()
             })
         })
         .collect::<Vec<_>>();
-    transition_states.sort();
-    transition_states.dedup();
+    referenced_states.sort();
+    referenced_states.dedup();
 
-    for state in transition_states.clone() {
+    for state in referenced_states {
         if !state_idents.contains(&state) {
-            return Err(
-                state.span().unwrap()
-                    .error("undefined state")
-                    .help(format!("add `{}` to the `states` block to resolve", state).as_str())
-                    .into()
-            );
+            let other = machine.regions.iter()
+                .filter_map(|other| Some((other.name.clone()?, other.states.get(&state)?)))
+                .next();
+
+            if let Some((other, definition)) = other {
+                state.span()
+                    .error(format!("state belongs to region `{}`", other).as_str())
+                    .help("transitions can only connect states of the same region")
+                    .span_note(definition.name.span(), "state is defined here")
+                    .emit();
+                continue;
+            }
+
+            undefined_state(&state, &state_idents);
+        }
+    }
+
+    // check if nested states are referred to by their full path
+    let transition_paths = region.transitions_by_event().into_iter()
+        .flat_map(|(_, transitions)| {
+            transitions.iter().flat_map(|transition| {
+                transition.from.paths().iter()
+                    .cloned()
+                    .chain(Some(transition.to_path.clone()))
+                    .collect::<Vec<_>>()
+            })
+        });
+
+    for path in transition_paths {
+        let expected = region.states.path(path.last().unwrap());
+        if !expected.is_empty() && path != expected {
+            let expected = expected.iter()
+                .map(|state| state.to_string())
+                .collect::<Vec<_>>()
+                .join("::");
+
+            path[0].span()
+                .error("invalid path to nested state")
+                .help(format!("refer to the state as `{}` to resolve", expected).as_str())
+                .emit();
+        }
+    }
+
+    // check if history transitions only lead into composite states
+    for (_, transitions) in region.transitions_by_event() {
+        for transition in transitions {
+            let is_composite = region.states.get(&transition.to)
+                .map(|state| state.is_composite())
+                .unwrap_or(true);
+
+            if transition.history.is_some() && !is_composite {
+                transition.to.span()
+                    .error("history requires a state with nested states")
+                    .help("remove the history marker or target a composite state to resolve")
+                    .emit();
+            }
+        }
+    }
+
+    check_determinism(region);
+
+    // check if each state belongs to at least one transition
+    for state in state_idents {
+        if !has_transitions(region, &state) {
+            state.span()
+                .warning(
+                    "state has no transitions and will never be reached or cause deadlock on construction"
+                )
+                .help(
+                    format!(
+                        "add a transition to resolve: `{} => OtherState : some_event`",
+                        state,
+                    ).as_str(),
+                )
+                .emit();
+        }
+    }
+}
+
+/// Reports every name which is defined more than once
+fn check_unique<'a, I: Iterator<Item = &'a Ident>>(names: I, kind: &str, help: &str) {
+    let mut defined: Vec<&Ident> = Vec::new();
+
+    for name in names {
+        match defined.iter().find(|other| **other == name) {
+            Some(original) => {
+                name.span()
+                    .error(format!("{} `{}` is defined multiple times", kind, name))
+                    .help(help)
+                    .span_note(original.span(), "first definition is here")
+                    .emit();
+            },
+            None => defined.push(name),
+        }
+    }
+}
+
+/// Reports events which are named differently but map to the same `Event` variant, e.g.
+/// `foo_bar` and `fooBar`
+fn check_event_variants(machine: &Machine) {
+    for (index, event) in machine.events.iter().enumerate() {
+        let variant = event.variant_name();
+        let original = machine.events[..index].iter()
+            .find(|other| other.name != event.name && other.variant_name() == variant);
+
+        if let Some(original) = original {
+            event.name.span()
+                .error(format!(
+                    "event `{}` maps to the same `Event::{}` variant as event `{}`",
+                    event.name,
+                    variant,
+                    original.name,
+                ))
+                .help("rename one of the events to resolve")
+                .span_note(original.name.span(), "other event is defined here")
+                .emit();
+        }
+    }
+}
+
+/// Reports events which would clash with a method generated on `Machine`, e.g. `restore`
+fn check_event_methods(machine: &Machine) {
+    let methods = generate::method_names(machine);
+
+    for event in machine.events.iter() {
+        let name = event.name.to_string();
+
+        if methods.contains(&name) {
+            event.name.span()
+                .error(format!(
+                    "event `{}` clashes with the generated method `Machine::{}`",
+                    name,
+                    name,
+                ))
+                .help("rename the event to resolve")
+                .emit();
+        }
+    }
+}
+
+pub fn parse_semantic(machine: Machine) -> StateMachineResult<Machine> {
+    // check if events, regions, states and actions are only defined once
+    check_unique(
+        machine.events.iter().map(|event| &event.name),
+        "event",
+        "rename or remove one of the events to resolve",
+    );
+    check_event_variants(&machine);
+    check_event_methods(&machine);
+    check_unique(
+        machine.regions.iter().filter_map(|region| region.name.as_ref()),
+        "region",
+        "rename or merge one of the regions to resolve",
+    );
+    check_unique(
+        machine.regions.iter()
+            .flat_map(|region| region.states.states.iter())
+            .map(|state| &state.name),
+        "state",
+        "state names must be unique throughout all levels and regions, rename one of the states \
+         to resolve",
+    );
+    check_unique(
+        machine.enter_actions.iter().map(|action| &action.state),
+        "entry action of state",
+        "merge both entry actions to resolve",
+    );
+    check_unique(
+        machine.exit_actions.iter().map(|action| &action.state),
+        "exit action of state",
+        "merge both exit actions to resolve",
+    );
+
+    for region in machine.regions.iter() {
+        parse_region_semantic(region, &machine);
+    }
+
+    // check if entry and exit actions only refer to known states
+    let state_idents = machine.regions.iter()
+        .flat_map(|region| region.states.states.iter())
+        .map(|state| state.name.clone())
+        .collect::<Vec<_>>();
+
+    for action in machine.enter_actions.iter().chain(machine.exit_actions.iter()) {
+        if !state_idents.contains(&action.state) {
+            undefined_state(&action.state, &state_idents);
         }
     }
 
@@ -31,20 +442,23 @@ pub fn parse_semantic(machine: Machine) -> StateMachineResult<Machine> {
     let event_idents = machine.events.iter()
         .map(|event| event.name.clone())
         .collect::<Vec<_>>();
-    let mut transition_events = machine.transitions.keys()
-        .map(|event| event.clone())
+    let mut transition_events = machine.regions.iter()
+        .flat_map(|region| region.transitions.keys())
+        .cloned()
         .collect::<Vec<_>>();
     transition_events.sort();
     transition_events.dedup();
 
     for event in transition_events.clone() {
         if !event_idents.contains(&event) {
-            return Err(
-                event.span().unwrap()
-                    .error("undefined event")
-                    .help(format!("add an event handler for `{}` to resolve", event).as_str())
-                    .into()
-            );
+            let error = event.span().error("undefined event");
+
+            match diagnostic::similar_name(&event.to_string(), &event_idents) {
+                Some(similar) => error.help(format!("did you mean `{}`?", similar).as_str()),
+                None => error.help(
+                    format!("add an event handler for `{}` to resolve", event).as_str(),
+                ),
+            }.emit();
         }
     }
 
@@ -52,7 +466,6 @@ pub fn parse_semantic(machine: Machine) -> StateMachineResult<Machine> {
     for event in event_idents {
         if !transition_events.contains(&event) {
             event.span()
-                .unwrap()
                 .warning(
                     "event will always fail as no valid transitions are defined for this event!"
                 )
@@ -66,25 +479,30 @@ pub fn parse_semantic(machine: Machine) -> StateMachineResult<Machine> {
         }
     }
 
-    // check if each state belongs to at least one transition
-    for state in state_idents {
-        if !transition_states.contains(&state) {
-            state.span()
-                .unwrap()
-                .warning(
-                    "state has no transitions and will never be reached or cause deadlock on construction"
-                )
-                .help(
-                    format!(
-                        "add a transition to resolve: `{} => OtherState : some_event`",
-                        state,
-                    ).as_str(),
-                )
+    // check if the typestate API can be generated for this machine
+    if let Some(typestate) = &machine.typestate {
+        if machine.regions.len() > 1 || machine.regions[0].name.is_some() {
+            typestate.span()
+                .error("typestate API is not supported for machines with regions")
+                .help("remove `typestate;` or merge the regions to resolve")
                 .emit();
         }
+
+        for event in machine.events.iter() {
+            if let Some(queue) = &event.queue_param_name {
+                queue.span()
+                    .error("raising events is not supported in typestate machines")
+                    .help("the state reached after raised events is only known at runtime")
+                    .emit();
+            }
+        }
     }
 
     // TODO: if $event_handle never used, warn that event never gets triggered
 
+    diagnostic::abort_if_errors()?;
+
+    check_reachability(&machine);
+
     Ok(machine)
 }
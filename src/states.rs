@@ -1,41 +1,151 @@
 use crate::error::{StateMachineResult, StateMachineError};
+use crate::events;
+use crate::diagnostic::SpanDiagnosticExt;
 
-use std::collections::HashMap;
 use proc_macro2::{TokenTree, Span, Ident, TokenStream, Delimiter, Spacing};
 use quote::quote;
-use syn::{Variant, Fields};
+use syn::{Variant, Fields, Attribute};
+
+pub struct State {
+    pub name: Ident,
+    pub parent: Option<Ident>,
+    pub children: Vec<Ident>,
+    pub attributes: Vec<Attribute>,
+    pub variant: TokenStream,
+    pub default: TokenStream,
+}
+
+impl State {
+    pub fn is_composite(&self) -> bool {
+        !self.children.is_empty()
+    }
+
+    /// The predicates of the `#[cfg(...)]` attributes of this state
+    fn cfg_predicates(&self) -> Vec<TokenStream> {
+        self.attributes.iter()
+            .filter(|attribute| attribute.path.is_ident("cfg"))
+            .filter_map(|attribute| match attribute.tts.clone().into_iter().next() {
+                Some(TokenTree::Group(group)) => Some(group.stream()),
+                _ => None,
+            })
+            .collect()
+    }
+}
 
 pub struct States {
     pub initial: Ident,
-    pub definition: TokenStream,
-    pub defaults: HashMap<Ident, TokenStream>,
+    pub states: Vec<State>,
 }
 
 impl Default for States {
     fn default() -> Self {
         Self {
             initial: Ident::new("__invalid__", Span::call_site()),
-            definition: TokenStream::new(),
-            defaults: HashMap::new(),
+            states: Vec::new(),
+        }
+    }
+}
+
+impl States {
+    pub fn get(&self, name: &Ident) -> Option<&State> {
+        self.states.iter().find(|state| state.name == *name)
+    }
+
+    pub fn leaves(&self) -> Vec<&State> {
+        self.states.iter()
+            .filter(|state| !state.is_composite())
+            .collect()
+    }
+
+    /// All states from the top-level state down to `name`.
+    pub fn path(&self, name: &Ident) -> Vec<Ident> {
+        let mut path = Vec::new();
+        let mut current = self.get(name);
+
+        while let Some(state) = current {
+            path.insert(0, state.name.clone());
+            current = state.parent.as_ref().and_then(|parent| self.get(parent));
+        }
+
+        path
+    }
+
+    /// Whether `ancestor` is `name` or one of its parent states.
+    pub fn contains(&self, ancestor: &Ident, name: &Ident) -> bool {
+        self.path(name).contains(ancestor)
+    }
+
+    /// The leaf state which is entered when entering `name`.
+    pub fn initial_leaf(&self, name: &Ident) -> Ident {
+        match self.get(name) {
+            Some(state) if state.is_composite() => self.initial_leaf(&state.children[0]),
+            _ => name.clone(),
+        }
+    }
+
+    /// The `#[cfg(...)]` attribute under which the items of `name` are compiled, which combines
+    /// the predicates of the state and of its parent states. Empty if the state is unconditional.
+    pub fn cfg(&self, name: &Ident) -> TokenStream {
+        let predicates = self.cfg_predicates(name);
+        match predicates.is_empty() {
+            true => quote!(),
+            false => quote!(#[cfg(all(#(#predicates),*))]),
         }
     }
+
+    /// Constant boolean expression which tells whether `name` is compiled, `None` if the state
+    /// is unconditional
+    pub fn is_compiled(&self, name: &Ident) -> Option<TokenStream> {
+        let predicates = self.cfg_predicates(name);
+        match predicates.is_empty() {
+            true => None,
+            false => Some(quote!(cfg!(all(#(#predicates),*)))),
+        }
+    }
+
+    fn cfg_predicates(&self, name: &Ident) -> Vec<TokenStream> {
+        self.path(name).iter()
+            .filter_map(|name| self.get(name))
+            .flat_map(State::cfg_predicates)
+            .collect()
+    }
+
+    /// All leaf states which are `name` or nested inside of `name`.
+    pub fn leaf_descendants(&self, name: &Ident) -> Vec<Ident> {
+        self.leaves().into_iter()
+            .filter(|leaf| self.contains(name, &leaf.name))
+            .map(|leaf| leaf.name.clone())
+            .collect()
+    }
 }
 
-struct State {
-    name: Ident,
-    default: TokenStream,
+/// Rejects `#[cfg(...)]` on the first state of a block, as the machine and every composite state
+/// must always have an initial state to enter
+fn check_initial(state: &State) -> StateMachineResult<()> {
+    match state.attributes.iter().find(|attribute| attribute.path.is_ident("cfg")) {
+        Some(attribute) => Err(attribute.pound_token.spans[0]
+            .error("the initial state cannot be conditionally compiled")
+            .help("move an unconditional state to the front to resolve")
+            .into()),
+        None => Ok(()),
+    }
 }
 
 fn parse_state(
-    iter: &mut Iterator<Item = TokenTree>,
+    iter: &mut dyn Iterator<Item = TokenTree>,
+    ancestors: &[Ident],
 ) -> StateMachineResult<State> {
-    let enum_item: Variant = syn::parse(iter.collect::<TokenStream>().into())
+    let mut enum_item: Variant = syn::parse(iter.collect::<TokenStream>().into())
         .map_err::<StateMachineError, _>(
-            |err| err.span().unwrap().error(format!("{}", err)).into()
+            |err| err.span().error(format!("{}", err)).into()
         )?;
-    let name = enum_item.ident;
+    let attributes = std::mem::take(&mut enum_item.attrs);
+    let name = enum_item.ident.clone();
+    let enum_name = ancestors.last()
+        .cloned()
+        .unwrap_or_else(|| Ident::new("State", Span::call_site()));
 
-    let default = match enum_item.fields {
+    let mut default = match enum_item.fields.clone() {
         Fields::Named(fields) => {
             let (idents, types): (Vec<_>, Vec<_>) = fields.named.into_iter()
                 .map(|field| (
@@ -44,7 +154,7 @@ fn parse_state(
                 )).unzip();
 
             quote! {
-                State::#name{
+                #enum_name::#name{
                     #(#idents: <#types as core::default::Default>::default(),)*
                 }
             }
@@ -55,88 +165,172 @@ fn parse_state(
                 .collect::<Vec<_>>();
 
             quote! {
-                State::#name(
+                #enum_name::#name(
                     #(<#types as core::default::Default>::default(),)*
                 )
             }
         },
         Fields::Unit => {
             quote! {
-                State::#name
+                #enum_name::#name
             }
         },
     };
 
+    // wrap the default value into the variants of all parent states
+    for (index, ancestor) in ancestors.iter().enumerate().rev() {
+        let enum_name = match index {
+            0 => Ident::new("State", Span::call_site()),
+            _ => ancestors[index - 1].clone(),
+        };
+
+        default = quote! {
+            #enum_name::#ancestor(#default)
+        };
+    }
+
     Ok(State {
         name,
+        parent: ancestors.last().cloned(),
+        children: Vec::new(),
+        attributes,
+        variant: quote! { #enum_item },
         default,
     })
 }
 
+fn parse_composite_state(
+    tokens: &[TokenTree],
+    ancestors: &[Ident],
+    states: &mut Vec<State>,
+) -> Option<StateMachineResult<Ident>> {
+    // skip outer attributes like `#[doc = "..."]`
+    let mut index = 0;
+    while let (Some(TokenTree::Punct(punct)), Some(TokenTree::Group(_))) =
+        (tokens.get(index), tokens.get(index + 1))
+    {
+        if punct.as_char() != '#' {
+            break;
+        }
+        index += 2;
+    }
+    let (attributes, tokens) = tokens.split_at(index);
+
+    if let [TokenTree::Ident(name), TokenTree::Punct(colon), TokenTree::Group(group)] = tokens {
+        if colon.as_char() == ':' && group.delimiter() == Delimiter::Brace {
+            let mut children_ancestors = ancestors.to_vec();
+            children_ancestors.push(name.clone());
+
+            let attributes = match events::parse_attributes(attributes.iter().cloned().collect()) {
+                Ok(attributes) => attributes,
+                Err(err) => return Some(Err(err)),
+            };
+
+            let position = states.len();
+            states.push(State {
+                name: name.clone(),
+                parent: ancestors.last().cloned(),
+                children: Vec::new(),
+                attributes,
+                variant: quote! { #name(#name) },
+                default: TokenStream::new(),
+            });
+
+            return Some(
+                parse_state_list(group.stream(), &children_ancestors, states)
+                    .and_then(|children| {
+                        // children which failed to parse have been reported already
+                        if group.stream().is_empty() {
+                            return Err(group.span().error(
+                                "expected at least one nested state"
+                            ).into());
+                        }
+
+                        states[position].children = children;
+                        Ok(name.clone())
+                    })
+            );
+        }
+    }
+
+    None
+}
+
+fn parse_state_tokens(
+    tokens: &[TokenTree],
+    ancestors: &[Ident],
+    states: &mut Vec<State>,
+) -> StateMachineResult<Ident> {
+    if let Some(result) = parse_composite_state(tokens, ancestors, states) {
+        return result;
+    }
+
+    let state = parse_state(&mut tokens.iter().cloned(), ancestors)?;
+    let name = state.name.clone();
+    states.push(state);
+
+    Ok(name)
+}
+
+fn parse_state_list(
+    stream: TokenStream,
+    ancestors: &[Ident],
+    states: &mut Vec<State>,
+) -> StateMachineResult<Vec<Ident>> {
+    let mut names = Vec::new();
+    let mut state = Vec::new();
+
+    for token in stream {
+        if let TokenTree::Punct(punct) = &token {
+            if punct.spacing() == Spacing::Alone && punct.as_char() == ',' {
+                match parse_state_tokens(&state, ancestors, states) {
+                    Ok(name) => names.push(name),
+                    Err(err) => err.recover()?,
+                }
+                state.clear();
+                continue;
+            }
+        }
+
+        state.push(token);
+    }
+
+    if !state.is_empty() {
+        match parse_state_tokens(&state, ancestors, states) {
+            Ok(name) => names.push(name),
+            Err(err) => err.recover()?,
+        }
+    }
+
+    if let Some(initial) = names.first().and_then(|name| states.iter().find(|state| state.name == *name)) {
+        if let Err(err) = check_initial(initial) {
+            err.recover()?;
+        }
+    }
+
+    Ok(names)
+}
+
 pub fn parse_states(
-    iter: &mut Iterator<Item = TokenTree>,
+    iter: &mut dyn Iterator<Item = TokenTree>,
     mut span: Span,
 ) -> StateMachineResult<States> {
-    let mut initial = Ident::new("__invalid__", span);
-    let mut defaults = HashMap::new();
-    let mut definition;
-
     if let Some(next) = iter.next() {
         span = next.span();
         if let TokenTree::Group(group) = next {
             if let Delimiter::Brace = group.delimiter() {
-                let mut state = Vec::new();
-                definition = group.stream();
-
-                for token in group.stream() {
-                    if let TokenTree::Punct(punct) = token.clone() {
-                        if let Spacing::Alone = punct.spacing() {
-                            if punct.as_char() == ',' {
-                                let mut iter = state.clone().into_iter();
-                                match parse_state(&mut iter) {
-                                    Err(err) => {
-                                        return Err(err);
-                                    },
-                                    Ok(state) => {
-                                        if initial.to_string() == "__invalid__" {
-                                            initial = state.name.clone();
-                                        }
-                                        defaults.insert(state.name, state.default);
-                                    }
-                                }
-
-                                state.clear();
-                                continue;
-                            }
-                        }
-                    }
-
-                    state.push(token);
-                }
-
-                if !state.is_empty() {
-                    let mut iter = state.clone().into_iter();
-                    match parse_state(&mut iter) {
-                        Err(err) => {
-                            return Err(err);
-                        },
-                        Ok(state) => {
-                            if initial.to_string() == "__invalid__" {
-                                initial = state.name.clone();
-                            }
-                            defaults.insert(state.name, state.default);
-                        }
-                    }
-                }
+                let mut states = Vec::new();
+                let names = parse_state_list(group.stream(), &[], &mut states)?;
 
                 return Ok(States {
-                    initial,
-                    definition,
-                    defaults,
+                    initial: names.first()
+                        .cloned()
+                        .unwrap_or_else(|| Ident::new("__invalid__", span)),
+                    states,
                 });
             }
         }
     }
 
-    Err(span.unwrap().error("expected states body `states { ... }`").into())
+    Err(span.error("expected states body `states { ... }`").into())
 }
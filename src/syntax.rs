@@ -1,32 +1,232 @@
 use crate::error::{StateMachineError, StateMachineResult};
+use crate::context;
+use crate::actions;
+use crate::actions::Action;
 use crate::events;
 use crate::events::Event;
 use crate::transitions;
-use crate::transitions::Transition;
 use crate::states;
-use crate::states::States;
+use crate::regions;
+use crate::regions::Region;
+use crate::queue;
+use crate::diagnostic;
+use crate::diagnostic::SpanDiagnosticExt;
 
-use std::collections::HashMap;
-use proc_macro2::{TokenStream, TokenTree, Ident, Delimiter, Span};
+use std::iter::Peekable;
+use proc_macro2::{TokenStream, TokenTree, Ident, Delimiter, Span, token_stream};
+use syn::{Type, Attribute};
 
 pub struct Machine {
+    pub attributes: Vec<Attribute>,
     pub name: Ident,
     pub events: Vec<Event>,
-    pub transitions: HashMap<Ident, Vec<Transition>>,
-    pub states: States,
+    pub regions: Vec<Region>,
+    pub context: Option<Type>,
+    pub enter_actions: Vec<Action>,
+    pub exit_actions: Vec<Action>,
+    pub max_depth: usize,
+    pub typestate: Option<Ident>,
 }
 
 impl Default for Machine {
     fn default() -> Self {
         Self {
+            attributes: Vec::new(),
             name: Ident::new("__invalid__", Span::call_site()),
             events: Vec::new(),
-            transitions: HashMap::new(),
-            states: States::default(),
+            regions: Vec::new(),
+            context: None,
+            enter_actions: Vec::new(),
+            exit_actions: Vec::new(),
+            max_depth: 16,
+            typestate: None,
         }
     }
 }
 
+fn implicit_region(machine: &mut Machine, span: Span) -> StateMachineResult<&mut Region> {
+    if machine.regions.is_empty() {
+        machine.regions.push(Region::new(None));
+    }
+
+    if machine.regions[0].name.is_some() {
+        return Err(span.error("expected definition inside of a region")
+            .help("move this definition into a `region name { ... }` block")
+            .into());
+    }
+
+    Ok(&mut machine.regions[0])
+}
+
+/// Collects outer attributes like `#[doc = "..."]` into `attributes` and returns the token
+/// following them
+fn parse_attributes(
+    mut next: TokenTree,
+    iter: &mut dyn Iterator<Item = TokenTree>,
+    attributes: &mut TokenStream,
+) -> StateMachineResult<TokenTree> {
+    loop {
+        let span = next.span();
+        if let TokenTree::Punct(punct) = &next {
+            if punct.as_char() == '#' {
+                if let Some(TokenTree::Group(group)) = iter.next() {
+                    if let Delimiter::Bracket = group.delimiter() {
+                        attributes.extend(vec![next, TokenTree::Group(group.clone())]);
+                        next = iter.next()
+                            .ok_or(group.span().error("expected item after attribute"))?;
+                        continue;
+                    }
+                }
+
+                return Err(span.error("expected attribute '#[...]'").into());
+            }
+        }
+
+        return Ok(next);
+    }
+}
+
+const KEYWORDS: &[&str] = &[
+    "event", "states", "transitions", "region", "context", "max_depth", "typestate", "on_enter",
+    "on_exit",
+];
+
+/// Skips the remains of an invalid definition. A definition ends with a block `{ ... }` or a
+/// semicolon `;`, and the next one starts with one of `keywords` or an attribute.
+pub fn skip_definition(iter: &mut Peekable<token_stream::IntoIter>, keywords: &[&str]) {
+    while let Some(next) = iter.peek() {
+        match next {
+            TokenTree::Ident(ident) if keywords.contains(&ident.to_string().as_str()) => return,
+            TokenTree::Punct(punct) if punct.as_char() == '#' => return,
+            _ => {},
+        }
+
+        match iter.next() {
+            Some(TokenTree::Group(ref group)) if group.delimiter() == Delimiter::Brace => return,
+            Some(TokenTree::Punct(ref punct)) if punct.as_char() == ';' => return,
+            _ => {},
+        }
+    }
+}
+
+/// Fails if `keyword` has been defined before, otherwise records its definition in `defined`
+pub fn check_duplicate_definition(
+    keyword: &Ident,
+    defined: &mut Vec<Ident>,
+) -> StateMachineResult<()> {
+    if let Some(original) = defined.iter().find(|other| *other == keyword) {
+        return Err(keyword.span()
+            .error(format!("duplicate `{}` definition", keyword))
+            .help(format!("merge or remove one of the `{}` definitions to resolve", keyword))
+            .span_note(original.span(), "first definition is here")
+            .into());
+    }
+
+    defined.push(keyword.clone());
+    Ok(())
+}
+
+fn parse_definition(
+    machine: &mut Machine,
+    defined: &mut Vec<Ident>,
+    next: TokenTree,
+    iter: &mut Peekable<token_stream::IntoIter>,
+) -> StateMachineResult<()> {
+    let mut attributes = TokenStream::new();
+    let next = parse_attributes(next, iter, &mut attributes)?;
+    let span = next.span();
+    if let TokenTree::Ident(ident) = next {
+        if !attributes.is_empty() && ident != "event" {
+            return Err(span
+                .error("attributes are only supported on events")
+                .help("add attributes to the `machine`, an `event` or a state to resolve")
+                .into());
+        }
+
+        // blocks and settings which may only be defined once
+        match ident.to_string().as_str() {
+            "states" | "transitions" | "context" | "max_depth" | "typestate" => {
+                check_duplicate_definition(&ident, defined)?;
+            },
+            _ => {},
+        }
+
+        match ident.to_string().as_str() {
+            "event" => {
+                let mut event = events::parse_event(iter, span)?;
+                event.attributes = events::parse_attributes(attributes)?;
+                machine.events.push(event);
+            },
+            "states" => {
+                implicit_region(machine, span)?.states =
+                    states::parse_states(iter, span)?;
+            },
+            "transitions" => {
+                implicit_region(machine, span)?.transitions =
+                    transitions::parse_transitions(iter, span)?;
+            },
+            "region" => {
+                if machine.regions.iter().any(|region| region.name.is_none()) {
+                    return Err(span
+                        .error("regions cannot be mixed with top-level states and transitions")
+                        .help("move the top-level `states` and `transitions` into a region")
+                        .into());
+                }
+
+                machine.regions.push(regions::parse_region(iter, span)?);
+            },
+            "context" => {
+                machine.context = Some(context::parse_context(
+                    iter,
+                    span,
+                )?);
+            },
+            "on_enter" => {
+                machine.enter_actions.push(actions::parse_action(
+                    iter,
+                    span,
+                )?);
+            },
+            "on_exit" => {
+                machine.exit_actions.push(actions::parse_action(
+                    iter,
+                    span,
+                )?);
+            },
+            "max_depth" => {
+                machine.max_depth = queue::parse_max_depth(iter, span)?;
+            },
+            "typestate" => {
+                match iter.next() {
+                    Some(TokenTree::Punct(ref punct)) if punct.as_char() == ';' => {
+                        machine.typestate = Some(ident);
+                    },
+                    _ => {
+                        return Err(span
+                            .error("expected semicolon ';' after typestate")
+                            .into());
+                    },
+                }
+            },
+            keyword => {
+                let error = span.error(
+                    "expected 'event', 'states', 'transitions', 'region', \
+                     'context', 'max_depth', 'typestate', 'on_enter', or 'on_exit' keyword"
+                );
+
+                return Err(match diagnostic::similar_name(keyword, KEYWORDS) {
+                    Some(similar) => error.help(format!("did you mean `{}`?", similar)),
+                    None => error,
+                }.into());
+            }
+        }
+    } else {
+        return Err(span.error("expected definition of machine").into());
+    }
+
+    Ok(())
+}
+
 pub fn parse_syntax(machine: TokenStream) -> StateMachineResult<Machine> {
     let mut iter = machine.into_iter();
     let mut machine = Machine::default();
@@ -34,68 +234,69 @@ pub fn parse_syntax(machine: TokenStream) -> StateMachineResult<Machine> {
     loop {
         let result = iter.next()
             .ok_or(StateMachineError::NoFurtherTokens)
+            .and_then(|next| {
+                let mut attributes = TokenStream::new();
+                let next = parse_attributes(next, &mut iter, &mut attributes)?;
+                machine.attributes = events::parse_attributes(attributes)?;
+
+                Ok(next)
+            })
             .and_then(|next| {
                 let span = next.span();
                 if let TokenTree::Ident(ident) = next {
-                    if ident.to_string() == "machine" {
-                        return iter.next()
-                            .ok_or(span.unwrap().error("missing machine name").into());
+                    if ident == "machine" {
+                        iter.next()
+                            .ok_or(span.error("missing machine name").into())
                     } else {
-                        return Err(span.unwrap().error(
-                            "invalid identifier, expected 'machine'"
-                        ).into());
+                        let error = span.error("invalid identifier, expected 'machine'");
+                        let similar = diagnostic::similar_name(&ident.to_string(), &["machine"]);
+
+                        Err(match similar {
+                            Some(similar) => error.help(format!("did you mean `{}`?", similar)),
+                            None => error,
+                        }.into())
                     }
                 } else {
-                    return Err(span.unwrap().error("unexpected token, expected 'machine'").into());
+                    Err(span.error("unexpected token, expected 'machine'").into())
                 }
             })
             .and_then(|next| {
                 let span = next.span();
                 if let TokenTree::Ident(ident) = next {
                     machine.name = ident;
-                    return iter.next()
-                        .ok_or(span.unwrap().error("missing machine definition").into());
+                    iter.next()
+                        .ok_or(span.error("missing machine definition").into())
                 } else {
-                    return Err(span.unwrap().error("invalid machine name").into());
+                    Err(span.error("invalid machine name").into())
                 }
             })
             .and_then(|next| {
                 let span = next.span();
                 if let TokenTree::Group(group) = next {
                     if let Delimiter::Brace = group.delimiter() {
-                        return Ok(group.stream().into_iter());
+                        Ok(group.stream().into_iter())
                     } else {
-                        return Err(span.unwrap().error("expected braces '{ ... }'").into());
+                        Err(span.error("expected braces '{ ... }'").into())
                     }
                 } else {
-                    return Err(span.unwrap().error("expected state machine definition").into());
+                    Err(span.error("expected state machine definition").into())
                 }
             })
-            .and_then(|mut group_iter| {
+            .and_then(|group_iter| {
+                let mut group_iter = group_iter.peekable();
+                let mut defined = Vec::new();
+
                 while let Some(next) = group_iter.next() {
-                    let span = next.span();
-                    if let TokenTree::Ident(ident) = next {
-                        match ident.to_string().as_str() {
-                            "event" => {
-                                machine.events.push(events::parse_event(&mut group_iter, span)?);
-                            },
-                            "states" => {
-                                machine.states = states::parse_states(&mut group_iter, span)?;
-                            },
-                            "transitions" => {
-                                machine.transitions = transitions::parse_transitions(
-                                    &mut group_iter,
-                                    span,
-                                )?;
-                            },
-                            _ => {
-                                return Err(span.unwrap().error(
-                                    "expected 'event', 'states', or 'transitions' keyword"
-                                ).into());
-                            }
-                        }
-                    } else {
-                        return Err(span.unwrap().error("expected definition of machine").into());
+                    let result = parse_definition(
+                        &mut machine,
+                        &mut defined,
+                        next,
+                        &mut group_iter,
+                    );
+
+                    if let Err(err) = result {
+                        err.recover()?;
+                        skip_definition(&mut group_iter, KEYWORDS);
                     }
                 }
 
@@ -104,10 +305,17 @@ pub fn parse_syntax(machine: TokenStream) -> StateMachineResult<Machine> {
 
         match result {
             Err(StateMachineError::NoFurtherTokens) => {
+                if machine.regions.is_empty() {
+                    machine.regions.push(Region::new(None));
+                }
+
+                // semantic checks would report follow-up errors on a partially parsed machine
+                diagnostic::abort_if_errors()?;
+
                 return Ok(machine);
             },
-            Err(StateMachineError::CompilationFailure(diagnostics)) => {
-                return Err(StateMachineError::CompilationFailure(diagnostics));
+            Err(err) => {
+                return Err(err);
             },
             Ok(()) => {
                 continue;
@@ -1,28 +1,239 @@
 use crate::error::{StateMachineError, StateMachineResult};
+use crate::states::States;
+use crate::diagnostic::SpanDiagnosticExt;
 
 use std::collections::HashMap;
-use proc_macro2::{TokenTree, Span, Ident, Delimiter, Spacing};
+use std::iter::Peekable;
+use proc_macro2::{TokenTree, TokenStream, Span, Ident, Group, Delimiter, Spacing, token_stream};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum History {
+    Shallow,
+    Deep,
+}
+
+#[derive(Debug, Clone)]
+pub enum Source {
+    /// `Paused | Running => ...`
+    States(Vec<Vec<Ident>>),
+    /// `* => ...` or `* - Stopped => ...`
+    Wildcard(Vec<Vec<Ident>>),
+}
+
+impl Source {
+    /// Paths of all states named in this source, including excluded ones
+    pub fn paths(&self) -> &[Vec<Ident>] {
+        match self {
+            Source::States(paths) => paths,
+            Source::Wildcard(except) => except,
+        }
+    }
+
+    /// All leaf states this source expands to
+    pub fn leaves(&self, states: &States) -> Vec<Ident> {
+        match self {
+            Source::States(paths) => {
+                let mut leaves = Vec::new();
+
+                for path in paths {
+                    for leaf in states.leaf_descendants(path.last().unwrap()) {
+                        if !leaves.contains(&leaf) {
+                            leaves.push(leaf);
+                        }
+                    }
+                }
+
+                leaves
+            },
+            Source::Wildcard(except) => {
+                states.leaves().into_iter()
+                    .map(|leaf| leaf.name.clone())
+                    .filter(|leaf| {
+                        !except.iter().any(|path| states.contains(path.last().unwrap(), leaf))
+                    })
+                    .collect()
+            },
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Transition {
-    pub from: Ident,
+    /// The first token of the transition
+    pub span: Span,
+    pub from: Source,
     pub to: Ident,
+    pub to_path: Vec<Ident>,
+    pub history: Option<History>,
+    pub guard: Option<TokenStream>,
+}
+
+/// Splits `transitions` into single-source transitions ordered by precedence: explicit
+/// transitions take precedence over wildcards and transitions of nested states take
+/// precedence over the ones of their parents
+pub fn by_precedence(transitions: &[Transition]) -> Vec<(Source, &Transition)> {
+    let mut sources = transitions.iter()
+        .flat_map(|transition| {
+            let sources = match &transition.from {
+                Source::States(paths) => paths.iter()
+                    .map(|path| (path.len(), Source::States(vec![path.clone()])))
+                    .collect::<Vec<_>>(),
+                Source::Wildcard(except) => vec![(0, Source::Wildcard(except.clone()))],
+            };
+
+            sources.into_iter()
+                .map(move |(depth, source)| (depth, source, transition))
+        })
+        .collect::<Vec<_>>();
+    sources.sort_by_key(|(depth, _, _)| std::cmp::Reverse(*depth));
+
+    sources.into_iter()
+        .map(|(_, source, transition)| (source, transition))
+        .collect()
+}
+
+fn is_punct(token: Option<&TokenTree>, ch: char) -> bool {
+    if let Some(TokenTree::Punct(punct)) = token {
+        return punct.as_char() == ch && punct.spacing() == Spacing::Alone;
+    }
+
+    false
+}
+
+fn parse_state_paths(
+    iter: &mut Peekable<token_stream::IntoIter>,
+    span: &mut Span,
+) -> StateMachineResult<Vec<Vec<Ident>>> {
+    let mut paths = Vec::new();
+
+    loop {
+        if let Some(next) = iter.next() {
+            *span = next.span();
+            if let TokenTree::Ident(ident) = next {
+                paths.push(parse_state_path(ident, iter, span)?);
+
+                if is_punct(iter.peek(), '|') {
+                    iter.next();
+                    continue;
+                }
+
+                return Ok(paths);
+            }
+        }
+
+        return Err(span.error("expected state identifier").into());
+    }
+}
+
+fn parse_history(group: Group) -> StateMachineResult<History> {
+    let tokens = group.stream().into_iter().collect::<Vec<_>>();
+
+    match tokens.as_slice() {
+        [TokenTree::Ident(ident)] if ident == "H" => {
+            return Ok(History::Shallow);
+        },
+        [TokenTree::Ident(ident), TokenTree::Punct(star)]
+            if ident == "H" && star.as_char() == '*' => {
+            return Ok(History::Deep);
+        },
+        _ => {},
+    }
+
+    Err(group.span()
+        .error("expected history marker")
+        .help("use `[H]` for shallow history or `[H*]` for deep history")
+        .into())
+}
+
+fn parse_state_path(
+    first: Ident,
+    iter: &mut Peekable<token_stream::IntoIter>,
+    span: &mut Span,
+) -> StateMachineResult<Vec<Ident>> {
+    let mut path = vec![first];
+
+    loop {
+        match iter.peek() {
+            Some(TokenTree::Punct(punct))
+                if punct.as_char() == ':' && punct.spacing() == Spacing::Joint => {},
+            _ => return Ok(path),
+        }
+
+        iter.next();
+        if let Some(next) = iter.next() {
+            *span = next.span();
+            if let TokenTree::Punct(punct) = next {
+                if punct.as_char() == ':' {
+                    if let Some(next) = iter.next() {
+                        *span = next.span();
+                        if let TokenTree::Ident(ident) = next {
+                            path.push(ident);
+                            continue;
+                        }
+                    }
+
+                    return Err(span.error("expected nested state identifier").into());
+                }
+            }
+        }
+
+        return Err(span.error("expected path separator '::'").into());
+    }
+}
+
+fn parse_guard(group: Group) -> StateMachineResult<TokenStream> {
+    let span = group.span();
+    let mut iter = group.stream().into_iter();
+
+    if let Some(TokenTree::Ident(ident)) = iter.next() {
+        if ident == "if" {
+            let guard = iter.collect::<TokenStream>();
+
+            if guard.is_empty() {
+                return Err(span.error("expected guard expression after 'if'").into());
+            }
+
+            return Ok(guard);
+        }
+    }
+
+    Err(span.error("expected guard condition '[if ...]'").into())
 }
 
 fn parse_transition(
-    iter: &mut Iterator<Item = TokenTree>,
+    iter: &mut Peekable<token_stream::IntoIter>,
     mut span: Span,
 ) -> StateMachineResult<(Ident, Transition)> {
+    let start = iter.peek().map(|token| token.span()).unwrap_or(span);
+
     iter.next()
         .ok_or(StateMachineError::NoFurtherTokens)
         .and_then(|next| {
             span = next.span();
+            if is_punct(Some(&next), '*') {
+                let mut except = Vec::new();
+
+                if is_punct(iter.peek(), '-') {
+                    iter.next();
+                    except = parse_state_paths(iter, &mut span)?;
+                }
+
+                return Ok(Source::Wildcard(except));
+            }
+
             if let TokenTree::Ident(ident) = next {
-                return Ok(ident);
+                let mut paths = vec![parse_state_path(ident, iter, &mut span)?];
+
+                if is_punct(iter.peek(), '|') {
+                    iter.next();
+                    paths.extend(parse_state_paths(iter, &mut span)?);
+                }
+
+                return Ok(Source::States(paths));
             }
 
-            Err(span.unwrap().error(
-                "expected state identifier for transition source state"
+            Err(span.error(
+                "expected state identifier or wildcard '*' for transition source state"
             ).into())
         })
         .and_then(|from| {
@@ -44,17 +255,28 @@ fn parse_transition(
                 }
             }
 
-            Err(span.unwrap().error("expected '=>' event transition operator").into())
+            Err(span.error("expected '=>' event transition operator").into())
         })
         .and_then(|from| {
             if let Some(next) = iter.next() {
                 span = next.span();
                 if let TokenTree::Ident(ident) = next {
-                    return Ok((from, ident));
+                    let to = parse_state_path(ident, iter, &mut span)?;
+                    let mut history = None;
+
+                    if let Some(TokenTree::Group(group)) = iter.peek().cloned() {
+                        if let Delimiter::Bracket = group.delimiter() {
+                            iter.next();
+                            span = group.span();
+                            history = Some(parse_history(group)?);
+                        }
+                    }
+
+                    return Ok((from, to, history));
                 }
             }
 
-            Err(span.unwrap().error(
+            Err(span.error(
                 "expected state identifier for transition destination state",
             ).into())
         })
@@ -70,40 +292,55 @@ fn parse_transition(
                 }
             }
 
-            Err(span.unwrap().error(
+            Err(span.error(
                 "expected colon ':' to specify associated event handler"
             ).into())
         })
-        .and_then(|(from, to)| {
+        .and_then(|(from, to, history)| {
             if let Some(next) = iter.next() {
                 span = next.span();
                 if let TokenTree::Ident(ident) = next {
                     return Ok((ident, Transition {
-                        from: from,
-                        to: to,
+                        span: start,
+                        from,
+                        to: to.last().unwrap().clone(),
+                        to_path: to,
+                        history,
+                        guard: None,
                     }));
                 }
             }
 
-            Err(span.unwrap().error("expected event handler name").into())
+            Err(span.error("expected event handler name").into())
         })
-        .and_then(|tuple| {
-            if let Some(next) = iter.next() {
-                if let TokenTree::Punct(punct) = next {
-                    if let Spacing::Alone = punct.spacing() {
-                        if punct.as_char() == ';' {
-                            return Ok(tuple);
-                        }
+        .and_then(|(event, mut transition)| {
+            let mut next = iter.next();
+
+            if let Some(TokenTree::Group(group)) = next.clone() {
+                if let Delimiter::Bracket = group.delimiter() {
+                    span = group.span();
+                    transition.guard = Some(parse_guard(group)?);
+                    next = iter.next();
+                }
+            }
+
+            Ok(((event, transition), next))
+        })
+        .and_then(|(tuple, next)| {
+            if let Some(TokenTree::Punct(punct)) = next {
+                if let Spacing::Alone = punct.spacing() {
+                    if punct.as_char() == ';' {
+                        return Ok(tuple);
                     }
                 }
             }
 
-            Err(span.unwrap().error("expected semicolon ';' at end of transition").into())
+            Err(span.error("expected semicolon ';' at end of transition").into())
         })
 }
 
 pub fn parse_transitions(
-    iter: &mut Iterator<Item = TokenTree>,
+    iter: &mut dyn Iterator<Item = TokenTree>,
     mut span: Span,
 ) -> StateMachineResult<HashMap<Ident, Vec<Transition>>> {
     let mut transitions: HashMap<Ident, Vec<Transition>> = HashMap::new();
@@ -112,14 +349,28 @@ pub fn parse_transitions(
         span = next.span();
         if let TokenTree::Group(group) = next {
             if let Delimiter::Brace = group.delimiter() {
-                let mut iter = group.stream().into_iter();
-                loop {
+                // parse each transition on its own, so an invalid transition does not affect
+                // the following ones
+                let mut statements = vec![Vec::new()];
+                for token in group.stream() {
+                    let end = is_punct(Some(&token), ';');
+                    statements.last_mut().unwrap().push(token);
+
+                    if end {
+                        statements.push(Vec::new());
+                    }
+                }
+
+                for statement in statements {
+                    let mut iter = statement.into_iter()
+                        .collect::<TokenStream>()
+                        .into_iter()
+                        .peekable();
+
                     match parse_transition(&mut iter, span) {
-                        Err(StateMachineError::NoFurtherTokens) => {
-                            return Ok(transitions);
-                        },
+                        Err(StateMachineError::NoFurtherTokens) => {},
                         Err(err) => {
-                            return Err(err);
+                            err.recover()?;
                         },
                         Ok((event, transition)) => {
                             if let Some(value) = transitions.get_mut(&event) {
@@ -130,9 +381,11 @@ pub fn parse_transitions(
                         }
                     }
                 }
+
+                return Ok(transitions);
             }
         }
     }
 
-    Err(span.unwrap().error("expected transitions body 'transitions { ... }'").into())
+    Err(span.error("expected transitions body 'transitions { ... }'").into())
 }
@@ -0,0 +1,174 @@
+use crate::syntax::Machine;
+use crate::states::States;
+use crate::transitions::{Transition, by_precedence};
+
+use proc_macro2::{Ident, TokenStream};
+use quote::quote;
+
+/// The leaf state reached by a transition from `leaf`, if it is known at compile time
+fn destination(transitions: &[Transition], states: &States, leaf: &Ident) -> Option<Ident> {
+    let mut destination = None;
+
+    for (source, transition) in by_precedence(transitions) {
+        if !source.leaves(states).contains(leaf) {
+            continue;
+        }
+
+        // a history transition resumes whatever state was active last
+        if transition.history.is_some() {
+            return None;
+        }
+
+        let to = states.initial_leaf(&transition.to);
+        match &destination {
+            Some(other) if *other != to => return None,
+            _ => destination = Some(to),
+        }
+
+        // all following transitions are shadowed by an unguarded one
+        if transition.guard.is_none() {
+            break;
+        }
+    }
+
+    destination
+}
+
+pub fn generate_typestate(machine: &Machine) -> TokenStream {
+    if machine.typestate.is_none() {
+        return TokenStream::new();
+    }
+
+    let region = &machine.regions[0];
+    let states = &region.states;
+    let leaves = states.leaves().into_iter()
+        .map(|state| state.name.clone())
+        .collect::<Vec<_>>();
+    let leaf_cfgs = leaves.iter()
+        .map(|leaf| states.cfg(leaf))
+        .collect::<Vec<_>>();
+    let initial = states.initial_leaf(&states.initial);
+
+    let (context_param, context_value) = match machine.context {
+        Some(_) => (quote! { context: Context }, quote! { context }),
+        None => (quote! {}, quote! {}),
+    };
+
+    let impls = leaves.iter()
+        .map(|leaf| {
+            let methods = machine.events.iter()
+                .filter_map(|event| {
+                    let name = &event.name;
+                    let to = destination(region.transitions.get(name)?, states, leaf)?;
+                    let (arguments, types): (Vec<_>, Vec<_>) = event.payload.iter()
+                        .map(|param| (param.name.clone(), param.ty.clone()))
+                        .unzip();
+                    let parameters = arguments.clone();
+                    let cfg = event.cfg_attributes();
+                    let to_cfg = states.cfg(&to);
+                    let attributes = event.method_attributes();
+
+                    Some(quote! {
+                        #cfg
+                        #to_cfg
+                        #attributes
+                        pub fn #name(
+                            mut self,
+                            #(#parameters: #types,)*
+                        ) -> core::result::Result<Machine<#to>, Rejected<#leaf>> {
+                            match self.machine.#name(#(#arguments,)*) {
+                                core::result::Result::Ok(()) => {
+                                    core::result::Result::Ok(self.cast())
+                                },
+                                core::result::Result::Err(error) => {
+                                    core::result::Result::Err(Rejected {
+                                        machine: self,
+                                        error,
+                                    })
+                                },
+                            }
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            let cfg = states.cfg(leaf);
+
+            quote! {
+                #cfg
+                #[allow(deprecated, clippy::too_many_arguments)]
+                impl Machine<#leaf> {
+                    #(#methods)*
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    quote! {
+        pub mod typestate {
+            #[allow(unused_imports)]
+            use super::*;
+
+            #(
+                #leaf_cfgs
+                #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+                pub struct #leaves;
+            )*
+
+            pub struct Machine<S> {
+                machine: super::Machine,
+                state: core::marker::PhantomData<S>,
+            }
+
+            pub struct Rejected<S> {
+                pub machine: Machine<S>,
+                pub error: TransitionError,
+            }
+
+            impl Machine<#initial> {
+                pub fn new(#context_param) -> Self {
+                    Machine {
+                        machine: super::Machine::new(#context_value),
+                        state: core::marker::PhantomData,
+                    }
+                }
+            }
+
+            impl<S> Machine<S> {
+                pub fn state(&self) -> &State {
+                    self.machine.state()
+                }
+
+                pub fn state_mut(&mut self) -> &mut State {
+                    self.machine.state_mut()
+                }
+
+                pub fn state_identifier(&self) -> StateIdentifier {
+                    self.machine.state_identifier()
+                }
+
+                pub fn context(&self) -> &Context {
+                    self.machine.context()
+                }
+
+                pub fn context_mut(&mut self) -> &mut Context {
+                    self.machine.context_mut()
+                }
+
+                pub fn into_inner(self) -> super::Machine {
+                    self.machine
+                }
+
+                #[allow(dead_code)]
+                fn cast<T>(self) -> Machine<T> {
+                    Machine {
+                        machine: self.machine,
+                        state: core::marker::PhantomData,
+                    }
+                }
+            }
+
+            #(#impls)*
+        }
+    }
+}
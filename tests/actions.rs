@@ -0,0 +1,110 @@
+use declarative_state_machine::state_machine;
+
+state_machine! {
+    machine heater {
+        context: Vec<&'static str>;
+
+        event start(_old: &mut State, _new: &mut State, log: &mut Context) {
+            log.push("handle start");
+        }
+
+        event work(_old: &mut State, _new: &mut State, log: &mut Context) {
+            log.push("handle work");
+        }
+
+        event stop(_old: &mut State, _new: &mut State, log: &mut Context) {
+            log.push("handle stop");
+        }
+
+        event refuse(_old: &mut State, _new: &mut State, log: &mut Context) {
+            log.push("handle refuse");
+            false
+        }
+
+        on_enter Off(_state: &mut State, log: &mut Context) { log.push("enter Off"); }
+        on_exit Off(_state: &mut State, log: &mut Context) { log.push("exit Off"); }
+        on_enter On(_state: &mut State, log: &mut Context) { log.push("enter On"); }
+        on_exit On(_state: &mut State, log: &mut Context) { log.push("exit On"); }
+        on_enter Idle(_state: &mut State, log: &mut Context) { log.push("enter Idle"); }
+        on_exit Idle(_state: &mut State, log: &mut Context) { log.push("exit Idle"); }
+        on_enter Busy(_state: &mut State, log: &mut Context) { log.push("enter Busy"); }
+        on_exit Busy(_state: &mut State, log: &mut Context) { log.push("exit Busy"); }
+
+        states {
+            Off,
+            On: {
+                Idle,
+                Busy,
+            },
+        }
+
+        transitions {
+            Off      => On       : start;
+            On::Idle => On::Busy : work;
+            On::Busy => On::Busy : work;
+            On       => Off      : stop;
+            Off      => On       : refuse;
+        }
+    }
+}
+
+use heater::Machine;
+
+fn drain(machine: &mut Machine) -> Vec<&'static str> {
+    core::mem::take(machine.context_mut())
+}
+
+#[test]
+fn construction_runs_no_actions() {
+    let mut machine = Machine::new(Vec::new());
+
+    assert!(drain(&mut machine).is_empty());
+}
+
+#[test]
+fn actions_run_after_the_handler_accepted() {
+    let mut machine = Machine::new(Vec::new());
+
+    machine.start().unwrap();
+    assert_eq!(drain(&mut machine), ["handle start", "exit Off", "enter On", "enter Idle"]);
+}
+
+#[test]
+fn vetoed_transition_runs_no_actions() {
+    let mut machine = Machine::new(Vec::new());
+
+    machine.refuse().unwrap_err();
+    assert_eq!(drain(&mut machine), ["handle refuse"]);
+}
+
+#[test]
+fn transition_between_sub_states_keeps_the_composite() {
+    let mut machine = Machine::new(Vec::new());
+    machine.start().unwrap();
+    drain(&mut machine);
+
+    machine.work().unwrap();
+    assert_eq!(drain(&mut machine), ["handle work", "exit Idle", "enter Busy"]);
+}
+
+#[test]
+fn self_transition_leaves_and_reenters_the_state() {
+    let mut machine = Machine::new(Vec::new());
+    machine.start().unwrap();
+    machine.work().unwrap();
+    drain(&mut machine);
+
+    machine.work().unwrap();
+    assert_eq!(drain(&mut machine), ["handle work", "exit Busy", "enter Busy"]);
+}
+
+#[test]
+fn leaving_a_composite_exits_from_the_inside_out() {
+    let mut machine = Machine::new(Vec::new());
+    machine.start().unwrap();
+    machine.work().unwrap();
+    drain(&mut machine);
+
+    machine.stop().unwrap();
+    assert_eq!(drain(&mut machine), ["handle stop", "exit Busy", "exit On", "enter Off"]);
+}
@@ -0,0 +1,146 @@
+use declarative_state_machine::state_machine;
+
+state_machine! {
+    machine engine {
+        event start(_old: &mut State, _new: &mut State) {}
+
+        event boost(_old: &mut State, _new: &mut State) {}
+
+        event cruise(_old: &mut State, _new: &mut State) {}
+
+        event stop(_old: &mut State, _new: &mut State) {}
+
+        event resume(_old: &mut State, _new: &mut State) {}
+
+        states {
+            Stopped,
+            #[cfg(any())]
+            Maintenance: {
+                Inspecting,
+                Repairing,
+            },
+            Running: {
+                Idle { rpm: u32 },
+                #[cfg(any())]
+                Turbo,
+                #[cfg(all())]
+                Cruising { speed: u32 },
+            },
+        }
+
+        transitions {
+            Stopped                 => Running                  : start;
+            Stopped                 => Maintenance              : stop;
+            Maintenance::Inspecting => Maintenance::Repairing   : start;
+            Running::Idle           => Running::Turbo           : boost;
+            Running::Idle           => Running::Cruising        : cruise;
+            Running                 => Stopped                  : stop;
+            Stopped                 => Running[H]               : resume;
+        }
+    }
+}
+
+state_machine! {
+    machine door {
+        typestate;
+        context: u32;
+
+        on_enter Locked(_state: &mut State, count: &mut Context) {
+            *count += 1;
+        }
+
+        on_enter Opened(_state: &mut State, count: &mut Context) {
+            *count += 1;
+        }
+
+        event open(_old: &mut State, _new: &mut State) {}
+
+        event lock(_old: &mut State, _new: &mut State) {}
+
+        event close(_old: &mut State, _new: &mut State) {}
+
+        states {
+            Closed,
+            #[cfg(any())]
+            Locked,
+            Opened,
+        }
+
+        transitions {
+            Closed  => Opened   : open;
+            Closed  => Locked   : lock;
+            Locked  => Closed   : open;
+            Opened  => Closed   : close;
+        }
+    }
+}
+
+use engine::{Machine, State, StateIdentifier, Running, TransitionErrorKind};
+
+#[test]
+fn transitions_into_disabled_states_are_not_generated() {
+    let mut machine = Machine::new();
+    machine.start().unwrap();
+
+    let error = machine.boost().unwrap_err();
+    assert_eq!(error.kind, TransitionErrorKind::NoTransition);
+    assert_eq!(machine.state_identifier(), StateIdentifier::Idle);
+}
+
+#[test]
+fn enabled_states_keep_working() {
+    let mut machine = Machine::new();
+    machine.start().unwrap();
+    machine.cruise().unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Cruising);
+    assert!(machine.is_running());
+
+    // the disabled `Maintenance` target leaves only the transition to `Stopped`
+    machine.stop().unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Stopped);
+
+    machine.resume().unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Cruising);
+}
+
+#[test]
+fn disabled_states_take_up_no_data() {
+    let mut machine = Machine::new();
+    let snapshot = machine.snapshot();
+    assert_eq!(snapshot.states.len(), 3);
+    assert_eq!(snapshot.history.len(), 1);
+
+    machine.start().unwrap();
+    if let State::Running(Running::Idle { rpm }) = machine.state_mut() {
+        *rpm = 800;
+    }
+    machine.cruise().unwrap();
+    machine.stop().unwrap();
+
+    let mut restored = Machine::new();
+    restored.restore(machine.snapshot()).unwrap();
+    assert_eq!(restored.snapshot(), machine.snapshot());
+    assert_eq!(restored.snapshot().states[1], State::Running(Running::Idle { rpm: 800 }));
+}
+
+#[test]
+fn disabled_states_cannot_be_parsed() {
+    assert_eq!("Running::Cruising".parse(), Ok(StateIdentifier::Cruising));
+    assert!("Running::Turbo".parse::<StateIdentifier>().is_err());
+    assert!("Maintenance::Inspecting".parse::<StateIdentifier>().is_err());
+}
+
+#[test]
+fn typestate_methods_into_disabled_states_are_not_generated() {
+    let machine = door::typestate::Machine::new(0);
+
+    let machine = machine.open().ok().unwrap();
+    assert_eq!(machine.state_identifier(), door::StateIdentifier::Opened);
+    assert_eq!(*machine.context(), 1);
+
+    let machine = machine.close().ok().unwrap();
+    assert_eq!(machine.state_identifier(), door::StateIdentifier::Closed);
+
+    let mut machine = machine.into_inner();
+    assert_eq!(machine.lock().unwrap_err().kind, door::TransitionErrorKind::NoTransition);
+}
@@ -0,0 +1,43 @@
+// `Idle` has no transitions on purpose, which the macro warns about
+#![allow(deprecated, dead_code)]
+
+use declarative_state_machine::state_machine;
+
+state_machine! {
+    machine drawing {
+        event run(_old: &mut State, _new: &mut State) {}
+
+        event stop(_old: &mut State, _new: &mut State) {}
+
+        states {
+            Stopped,
+            Running: {
+                Busy,
+                Idle,
+            },
+        }
+
+        transitions {
+            Stopped => Running : run;
+            Running => Stopped : stop;
+        }
+    }
+}
+
+#[test]
+fn mermaid_declares_states_without_transitions() {
+    let lines = drawing::MERMAID.lines().map(str::trim).collect::<Vec<_>>();
+
+    assert!(lines.contains(&"state Running {"));
+    assert!(lines.contains(&"Idle"));
+    assert!(lines.contains(&"Busy"));
+}
+
+#[test]
+fn plantuml_declares_states_without_transitions() {
+    let lines = drawing::PLANTUML.lines().map(str::trim).collect::<Vec<_>>();
+
+    assert!(lines.contains(&"state Running {"));
+    assert!(lines.contains(&"state Idle"));
+    assert!(lines.contains(&"state Busy"));
+}
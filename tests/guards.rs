@@ -0,0 +1,69 @@
+use declarative_state_machine::state_machine;
+
+state_machine! {
+    machine charger {
+        context: u32;
+
+        event charge(_old: &mut State, _new: &mut State, charged: &mut Context, level: u32) {
+            *charged = level;
+        }
+
+        event reset(_old: &mut State, _new: &mut State) {}
+
+        states {
+            Empty,
+            Half,
+            Full,
+        }
+
+        transitions {
+            Empty => Full  : charge [if level >= 100];
+            Empty => Half  : charge [if level >= 50];
+            Half  => Full  : charge [if level >= 50];
+            Half  => Empty : reset;
+            Full  => Empty : reset;
+        }
+    }
+}
+
+use charger::{Machine, StateIdentifier, TransitionErrorKind};
+
+#[test]
+fn first_matching_guard_is_taken() {
+    let mut machine = Machine::new(0);
+
+    machine.charge(100).unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Full);
+    assert_eq!(*machine.context(), 100);
+}
+
+#[test]
+fn rejected_guard_falls_through_to_next_transition() {
+    let mut machine = Machine::new(0);
+
+    machine.charge(60).unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Half);
+
+    machine.charge(60).unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Full);
+}
+
+#[test]
+fn event_fails_when_all_guards_reject() {
+    let mut machine = Machine::new(0);
+
+    let error = machine.charge(10).unwrap_err();
+    assert_eq!(error.kind, TransitionErrorKind::GuardRejected);
+    assert_eq!(error.state, StateIdentifier::Empty);
+    assert_eq!(machine.state_identifier(), StateIdentifier::Empty);
+    assert_eq!(*machine.context(), 0);
+}
+
+#[test]
+fn event_fails_without_transition() {
+    let mut machine = Machine::new(0);
+
+    let error = machine.reset().unwrap_err();
+    assert_eq!(error.kind, TransitionErrorKind::NoTransition);
+    assert_eq!(error.event, "reset");
+}
@@ -0,0 +1,81 @@
+use declarative_state_machine::state_machine;
+
+state_machine! {
+    machine conveyor {
+        event start(_old: &mut State, _new: &mut State) {}
+
+        event speed_up(_old: &mut State, _new: &mut State) {}
+
+        event boost(_old: &mut State, _new: &mut State) {}
+
+        event pause(_old: &mut State, _new: &mut State) {}
+
+        event resume(_old: &mut State, _new: &mut State) {}
+
+        event resume_deep(_old: &mut State, _new: &mut State) {}
+
+        states {
+            Paused,
+            Operational: {
+                Slow,
+                Fast: {
+                    Low,
+                    High,
+                },
+            },
+        }
+
+        transitions {
+            Paused                      => Operational                  : start;
+            Operational::Slow           => Operational::Fast            : speed_up;
+            Operational::Fast::Low      => Operational::Fast::High      : boost;
+            Operational                 => Paused                       : pause;
+            Paused                      => Operational[H]               : resume;
+            Paused                      => Operational[H*]              : resume_deep;
+        }
+    }
+}
+
+use conveyor::{Machine, StateIdentifier};
+
+fn paused_in_high() -> Machine {
+    let mut machine = Machine::new();
+    machine.start().unwrap();
+    machine.speed_up().unwrap();
+    machine.boost().unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::High);
+    machine.pause().unwrap();
+    machine
+}
+
+#[test]
+fn history_without_previous_visit_enters_initial_state() {
+    let mut machine = Machine::new();
+
+    machine.resume().unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Slow);
+}
+
+#[test]
+fn shallow_history_resumes_direct_sub_state() {
+    let mut machine = paused_in_high();
+
+    machine.resume().unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Low);
+}
+
+#[test]
+fn deep_history_resumes_nested_state() {
+    let mut machine = paused_in_high();
+
+    machine.resume_deep().unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::High);
+}
+
+#[test]
+fn plain_transition_ignores_history() {
+    let mut machine = paused_in_high();
+
+    machine.start().unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Slow);
+}
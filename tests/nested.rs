@@ -0,0 +1,67 @@
+use declarative_state_machine::state_machine;
+
+state_machine! {
+    machine worker {
+        event start(_old: &mut State, _new: &mut State) {}
+
+        event work(_old: &mut State, _new: &mut State) {}
+
+        event stop(_old: &mut State, _new: &mut State) {}
+
+        states {
+            Stopped,
+            Running: {
+                Idle,
+                Busy(u32),
+            },
+        }
+
+        transitions {
+            Stopped       => Running       : start;
+            Running::Idle => Running::Busy : work;
+            Running::Busy => Running::Idle : stop;
+            Running       => Stopped       : stop;
+        }
+    }
+}
+
+use worker::{Machine, StateIdentifier};
+
+#[test]
+fn transition_into_composite_enters_first_sub_state() {
+    let mut machine = Machine::new();
+    assert!(!machine.is_running());
+
+    machine.start().unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Idle);
+    assert!(machine.is_running());
+    assert!(machine.is_idle());
+    assert!(!machine.is_busy());
+}
+
+#[test]
+fn sub_state_transition_takes_precedence_over_composite() {
+    let mut machine = Machine::new();
+    machine.start().unwrap();
+    machine.work().unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Busy);
+
+    machine.stop().unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Idle);
+}
+
+#[test]
+fn composite_transition_applies_to_sub_states() {
+    let mut machine = Machine::new();
+    machine.start().unwrap();
+
+    machine.stop().unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Stopped);
+    assert!(!machine.is_running());
+}
+
+#[test]
+fn state_identifiers_know_their_parent() {
+    assert_eq!(StateIdentifier::Busy.parent(), Some(StateIdentifier::Running));
+    assert_eq!(StateIdentifier::Running.parent(), None);
+}
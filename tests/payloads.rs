@@ -0,0 +1,71 @@
+use declarative_state_machine::state_machine;
+
+state_machine! {
+    machine reporter {
+        context: Vec<u32>;
+
+        event fail(
+            _old: &mut State,
+            _new: &mut State,
+            log: &mut Context,
+            error: u32,
+            source: u32,
+            target: u32,
+            to: u32,
+            checkpoint: u32,
+            fired: u32,
+        ) {
+            log.extend_from_slice(&[error, source, target, to, checkpoint, fired]);
+        }
+
+        event recover(_old: &mut State, _new: &mut State, level: u32) {
+            level > 0
+        }
+
+        states {
+            Working,
+            Failed,
+        }
+
+        transitions {
+            Working => Failed  : fail [if error > 0];
+            Failed  => Working : recover [if level > source()];
+        }
+    }
+}
+
+fn source() -> u32 {
+    0
+}
+
+use reporter::{Machine, StateIdentifier, TransitionErrorKind};
+
+#[test]
+fn payload_names_do_not_clash_with_generated_code() {
+    let mut machine = Machine::new(Vec::new());
+
+    machine.fail(1, 2, 3, 4, 5, 6).unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Failed);
+    assert_eq!(machine.context(), &[1, 2, 3, 4, 5, 6]);
+}
+
+#[test]
+fn guard_sees_payload_named_like_generated_locals() {
+    let mut machine = Machine::new(Vec::new());
+
+    let error = machine.fail(0, 2, 3, 4, 5, 6).unwrap_err();
+    assert_eq!(error.kind, TransitionErrorKind::GuardRejected);
+    assert!(machine.context().is_empty());
+}
+
+#[test]
+fn guard_calls_function_named_like_generated_locals() {
+    let mut machine = Machine::new(Vec::new());
+    machine.fail(1, 2, 3, 4, 5, 6).unwrap();
+
+    let error = machine.recover(0).unwrap_err();
+    assert_eq!(error.kind, TransitionErrorKind::GuardRejected);
+
+    machine.recover(1).unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Working);
+}
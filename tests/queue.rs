@@ -0,0 +1,98 @@
+use declarative_state_machine::state_machine;
+
+state_machine! {
+    machine relay {
+        context: u32;
+        max_depth: 4;
+
+        event begin(_old: &mut State, _new: &mut State, queue: &mut Queue) {
+            queue.raise(Event::Finish);
+        }
+
+        event refuse(_old: &mut State, _new: &mut State, queue: &mut Queue) {
+            queue.raise(Event::Finish);
+            false
+        }
+
+        event finish(_old: &mut State, _new: &mut State, finished: &mut Context) {
+            *finished += 1;
+        }
+
+        event bounce(_old: &mut State, _new: &mut State, queue: &mut Queue) {
+            queue.raise(Event::Bounce);
+        }
+
+        event flood(_old: &mut State, _new: &mut State, queue: &mut Queue) {
+            for _ in 0..8 {
+                queue.raise(Event::Finish);
+            }
+            false
+        }
+
+        event reset(_old: &mut State, _new: &mut State) {}
+
+        states {
+            Idle,
+            Working,
+            Done,
+        }
+
+        transitions {
+            Idle    => Working : begin;
+            Idle    => Working : refuse;
+            Idle    => Working : flood;
+            Working => Done    : finish;
+            Done    => Done    : bounce;
+            Done    => Idle    : reset;
+        }
+    }
+}
+
+use relay::{Event, Machine, StateIdentifier, TransitionErrorKind};
+
+#[test]
+fn raised_events_run_to_completion() {
+    let mut machine = Machine::new(0);
+
+    machine.begin().unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Done);
+    assert_eq!(*machine.context(), 1);
+}
+
+#[test]
+fn events_raised_by_vetoing_handler_are_discarded() {
+    let mut machine = Machine::new(0);
+
+    let error = machine.refuse().unwrap_err();
+    assert_eq!(error.kind, TransitionErrorKind::HandlerVetoed);
+    assert_eq!(machine.state_identifier(), StateIdentifier::Idle);
+    assert_eq!(*machine.context(), 0);
+}
+
+#[test]
+fn endless_raising_exceeds_max_depth() {
+    let mut machine = Machine::new(0);
+    machine.begin().unwrap();
+
+    let error = machine.dispatch(Event::Bounce).unwrap_err();
+    assert_eq!(error.kind, TransitionErrorKind::MaxDepthExceeded);
+    assert_eq!(error.event, "bounce");
+
+    // the queue is cleared, so the machine keeps working
+    machine.reset().unwrap();
+    machine.begin().unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Done);
+    assert_eq!(*machine.context(), 2);
+}
+
+#[test]
+fn overflow_of_vetoing_handler_is_discarded() {
+    let mut machine = Machine::new(0);
+
+    let error = machine.flood().unwrap_err();
+    assert_eq!(error.kind, TransitionErrorKind::HandlerVetoed);
+
+    machine.begin().unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Done);
+    assert_eq!(*machine.context(), 1);
+}
@@ -0,0 +1,79 @@
+use declarative_state_machine::state_machine;
+
+state_machine! {
+    machine device {
+        context: Vec<String>;
+
+        event connect(_old: &mut State, _new: &mut State) {}
+
+        event shutdown(_old: &mut State, _new: &mut State, log: &mut Context, reason: String) {
+            log.push(reason);
+        }
+
+        region connectivity {
+            states { Offline, Online }
+            transitions {
+                Offline => Online  : connect;
+                Online  => Offline : shutdown;
+            }
+        }
+
+        region power {
+            states { On, Off }
+            transitions {
+                On => Off : shutdown;
+            }
+        }
+    }
+}
+
+use device::{Event, Machine, StateIdentifier, TransitionErrorKind};
+
+#[test]
+fn regions_start_in_their_initial_states() {
+    let machine = Machine::new(Vec::new());
+
+    assert_eq!(machine.connectivity_state_identifier(), StateIdentifier::Offline);
+    assert_eq!(machine.power_state_identifier(), StateIdentifier::On);
+}
+
+#[test]
+fn event_only_changes_regions_handling_it() {
+    let mut machine = Machine::new(Vec::new());
+
+    machine.connect().unwrap();
+    assert_eq!(machine.connectivity_state_identifier(), StateIdentifier::Online);
+    assert_eq!(machine.power_state_identifier(), StateIdentifier::On);
+}
+
+/// The handler receives the states of a single transition, so it runs once per region
+#[test]
+fn event_fires_in_every_region() {
+    let mut machine = Machine::new(Vec::new());
+    machine.connect().unwrap();
+
+    machine.dispatch(Event::Shutdown { reason: "maintenance".to_string() }).unwrap();
+    assert_eq!(machine.connectivity_state_identifier(), StateIdentifier::Offline);
+    assert_eq!(machine.power_state_identifier(), StateIdentifier::Off);
+    assert_eq!(machine.context(), &["maintenance", "maintenance"]);
+}
+
+#[test]
+fn event_succeeds_if_one_region_changed() {
+    let mut machine = Machine::new(Vec::new());
+
+    machine.shutdown("low battery".to_string()).unwrap();
+    assert_eq!(machine.connectivity_state_identifier(), StateIdentifier::Offline);
+    assert_eq!(machine.power_state_identifier(), StateIdentifier::Off);
+    assert_eq!(machine.context(), &["low battery"]);
+}
+
+#[test]
+fn event_fails_if_no_region_changed() {
+    let mut machine = Machine::new(Vec::new());
+    machine.shutdown("low battery".to_string()).unwrap();
+
+    let error = machine.shutdown("again".to_string()).unwrap_err();
+    assert_eq!(error.kind, TransitionErrorKind::NoTransition);
+    assert_eq!(machine.context(), &["low battery"]);
+}
@@ -0,0 +1,90 @@
+use declarative_state_machine::state_machine;
+
+state_machine! {
+    machine lamp {
+        context: u32;
+
+        event switch_on(_old: &mut State, _new: &mut State) {}
+
+        event brighten(_old: &mut State, new: &mut State, level: u8) {
+            *new = State::On(On::Bright(level));
+        }
+
+        event switch_off(_old: &mut State, _new: &mut State) {}
+
+        event resume(_old: &mut State, _new: &mut State) {}
+
+        on_enter Bright(_state: &mut State, entered: &mut Context) {
+            *entered += 1;
+        }
+
+        states {
+            Off,
+            On: {
+                Dim,
+                Bright(u8),
+            },
+        }
+
+        transitions {
+            Off     => On         : switch_on;
+            On::Dim => On::Bright : brighten;
+            On      => Off        : switch_off;
+            Off     => On[H]      : resume;
+        }
+    }
+}
+
+use lamp::{Machine, On, RestoreError, State, StateIdentifier};
+
+fn bright() -> Machine {
+    let mut machine = Machine::new(0);
+    machine.switch_on().unwrap();
+    machine.brighten(7).unwrap();
+    machine
+}
+
+#[test]
+fn restore_puts_snapshot_back_without_actions() {
+    let mut machine = bright();
+    let snapshot = machine.snapshot();
+
+    machine.switch_off().unwrap();
+    machine.restore(snapshot).unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Bright);
+    assert_eq!(machine.state(), &State::On(On::Bright(7)));
+    assert_eq!(*machine.context(), 1);
+
+    machine.switch_off().unwrap();
+    machine.resume().unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Bright);
+}
+
+#[test]
+fn restore_rejects_composite_current_state() {
+    let mut machine = bright();
+    let mut snapshot = machine.snapshot();
+    snapshot.current_states[0] = StateIdentifier::On;
+
+    assert_eq!(machine.restore(snapshot), Err(RestoreError::InvalidState(StateIdentifier::On)));
+    assert_eq!(machine.state_identifier(), StateIdentifier::Bright);
+}
+
+#[test]
+fn restore_rejects_misplaced_state_data() {
+    let mut machine = bright();
+    let mut snapshot = machine.snapshot();
+    snapshot.states.swap(0, 1);
+
+    assert!(matches!(machine.restore(snapshot), Err(RestoreError::InvalidStateData(_))));
+    assert_eq!(machine.state(), &State::On(On::Bright(7)));
+}
+
+#[test]
+fn restore_rejects_history_outside_composite() {
+    let mut machine = bright();
+    let mut snapshot = machine.snapshot();
+    snapshot.history[0] = StateIdentifier::Off;
+
+    assert_eq!(machine.restore(snapshot), Err(RestoreError::InvalidHistory(StateIdentifier::On)));
+}
@@ -0,0 +1,77 @@
+use declarative_state_machine::state_machine;
+
+state_machine! {
+    machine motor {
+        typestate;
+        context: u32;
+
+        event run(_old: &mut State, _new: &mut State, level: &mut Context, charge: u32) {
+            *level = charge;
+        }
+
+        event pause(_old: &mut State, _new: &mut State) {}
+
+        event stop(_old: &mut State, _new: &mut State) {}
+
+        event charge(_old: &mut State, _new: &mut State, level: &mut Context, amount: u32) {
+            *level += amount;
+        }
+
+        states {
+            Stopped,
+            Running,
+            Paused,
+            Charging,
+            Full,
+        }
+
+        transitions {
+            Stopped | Paused                    => Running  : run [if charge > 10];
+            Running                             => Paused   : pause;
+            Running | Paused | Charging | Full  => Stopped  : stop;
+            Stopped                             => Charging : charge [if amount < 50];
+            Stopped                             => Full     : charge;
+        }
+    }
+}
+
+use motor::typestate::{Machine, Paused, Running, Stopped};
+use motor::{StateIdentifier, TransitionErrorKind};
+
+#[test]
+fn events_move_between_state_types() {
+    let machine: Machine<Stopped> = Machine::new(0);
+
+    let machine: Machine<Running> = machine.run(20).ok().unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Running);
+    assert_eq!(*machine.context(), 20);
+
+    let machine: Machine<Paused> = machine.pause().ok().unwrap();
+    let machine: Machine<Running> = machine.run(30).ok().unwrap();
+    let machine: Machine<Stopped> = machine.stop().ok().unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Stopped);
+}
+
+#[test]
+fn rejected_event_hands_back_the_machine() {
+    let machine: Machine<Paused> = Machine::new(0).run(20).ok().unwrap().pause().ok().unwrap();
+
+    let rejected = machine.run(5).err().unwrap();
+    assert_eq!(rejected.error.kind, TransitionErrorKind::GuardRejected);
+    assert_eq!(rejected.error.state, StateIdentifier::Paused);
+
+    let machine: Machine<Paused> = rejected.machine;
+    assert_eq!(machine.state_identifier(), StateIdentifier::Paused);
+    assert_eq!(*machine.context(), 20);
+
+    let machine: Machine<Running> = machine.run(15).ok().unwrap();
+    assert_eq!(*machine.context(), 15);
+}
+
+#[test]
+fn events_with_runtime_destination_use_the_dynamic_machine() {
+    let mut machine = Machine::new(0).into_inner();
+
+    machine.charge(60).unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Full);
+}
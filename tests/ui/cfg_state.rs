@@ -0,0 +1,23 @@
+use declarative_state_machine::state_machine;
+
+state_machine! {
+    machine conditional {
+        event run(_old: &mut State, _new: &mut State) {}
+
+        states {
+            #[cfg(feature = "turbo")]
+            Stopped,
+            Running: {
+                #[cfg(feature = "turbo")]
+                Boosted,
+                Normal,
+            },
+        }
+
+        transitions {
+            Stopped => Running : run;
+        }
+    }
+}
+
+fn main() {}
@@ -0,0 +1,59 @@
+use declarative_state_machine::state_machine;
+
+state_machine! {
+    machine twice {
+        event run(_old: &mut State, _new: &mut State) {}
+
+        event run(_old: &mut State, _new: &mut State) {}
+
+        states {
+            Stopped,
+            Running,
+            Stopped,
+        }
+
+        transitions {
+            Stopped => Running : run;
+            Running => Stopped : run;
+        }
+    }
+}
+
+state_machine! {
+    machine blocks {
+        event run(_old: &mut State, _new: &mut State) {}
+
+        states {
+            Stopped,
+            Running,
+        }
+
+        transitions {
+            Stopped => Running : run;
+        }
+
+        transitions {
+            Running => Stopped : run;
+        }
+    }
+}
+
+state_machine! {
+    machine clashing {
+        event restore(_old: &mut State, _new: &mut State) {}
+
+        event is_stopped(_old: &mut State, _new: &mut State) {}
+
+        states {
+            Stopped,
+            Running,
+        }
+
+        transitions {
+            Stopped => Running : restore;
+            Running => Stopped : is_stopped;
+        }
+    }
+}
+
+fn main() {}
@@ -0,0 +1,21 @@
+use declarative_state_machine::state_machine;
+
+state_machine! {
+    machine colliding {
+        event foo_bar(_old: &mut State, _new: &mut State) {}
+
+        event fooBar(_old: &mut State, _new: &mut State) {}
+
+        states {
+            Stopped,
+            Running,
+        }
+
+        transitions {
+            Stopped => Running : foo_bar;
+            Running => Stopped : fooBar;
+        }
+    }
+}
+
+fn main() {}
@@ -0,0 +1,30 @@
+use declarative_state_machine::state_machine;
+
+state_machine! {
+    machine ambiguous {
+        event run(_old: &mut State, _new: &mut State) {}
+
+        event stop(_old: &mut State, _new: &mut State) {}
+
+        states {
+            Stopped,
+            Running,
+            Paused,
+        }
+
+        transitions {
+            Stopped => Running : run;
+            Stopped => Paused  : run;
+            Running => Stopped : stop [if ready()];
+            Running => Paused  : stop [if ready()];
+            Paused  => Stopped : stop;
+            Paused  => Stopped : stop;
+        }
+    }
+}
+
+fn ready() -> bool {
+    true
+}
+
+fn main() {}
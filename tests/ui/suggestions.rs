@@ -0,0 +1,27 @@
+use declarative_state_machine::state_machine;
+
+state_machine! {
+    machine misspelled {
+        event run(_old: &mut State, _new: &mut State) {}
+
+        event stop(_old: &mut State, _new: &mut State) {}
+
+        states {
+            Stopped,
+            Running,
+        }
+
+        transitions {
+            Stopped => Runing  : run;
+            Running => Stopped : sto;
+        }
+    }
+}
+
+state_machine! {
+    machine typo {
+        evnt run(_old: &mut State, _new: &mut State) {}
+    }
+}
+
+fn main() {}
@@ -0,0 +1,20 @@
+use declarative_state_machine::state_machine;
+
+state_machine! {
+    machine broken {
+        event run(_old: &mut State, _new: &mut State) {}
+
+        states {
+            Stopped,
+            42,
+            Running,
+        }
+
+        transitions {
+            Stopped => Running run;
+            Running => : run;
+        }
+    }
+}
+
+fn main() {}
@@ -0,0 +1,20 @@
+use declarative_state_machine::state_machine;
+
+state_machine! {
+    machine parallel {
+        typestate;
+
+        event run(_old: &mut State, _new: &mut State, queue: &mut Queue) {
+            queue.raise(Event::Run);
+        }
+
+        region engine {
+            states { Stopped, Running }
+            transitions {
+                Stopped => Running : run;
+            }
+        }
+    }
+}
+
+fn main() {}
@@ -0,0 +1,41 @@
+use declarative_state_machine::state_machine;
+
+state_machine! {
+    machine motor {
+        typestate;
+
+        event run(_old: &mut State, _new: &mut State) {}
+
+        event pause(_old: &mut State, _new: &mut State) {}
+
+        event charge(_old: &mut State, _new: &mut State, amount: u32) {
+            amount > 0
+        }
+
+        states {
+            Stopped,
+            Running,
+            Paused,
+            Charging,
+            Full,
+        }
+
+        transitions {
+            Stopped | Paused => Running  : run;
+            Running          => Paused   : pause;
+            Stopped          => Charging : charge [if amount < 50];
+            Stopped          => Full     : charge;
+            Charging | Full  => Stopped  : run;
+        }
+    }
+}
+
+fn main() {
+    let machine = motor::typestate::Machine::new();
+
+    // `pause` is only valid in `Running`
+    let _ = machine.pause();
+
+    // the destination of `charge` is only known at runtime
+    let _ = motor::typestate::Machine::new().charge(10);
+}
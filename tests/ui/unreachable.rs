@@ -0,0 +1,26 @@
+#![deny(deprecated)]
+
+use declarative_state_machine::state_machine;
+
+state_machine! {
+    machine island {
+        event run(_old: &mut State, _new: &mut State) {}
+
+        event rescue(_old: &mut State, _new: &mut State) {}
+
+        states {
+            Stopped,
+            Running,
+            Stranded,
+            Rescued,
+        }
+
+        transitions {
+            Stopped  => Running : run;
+            Running  => Stopped : run;
+            Stranded => Rescued : rescue;
+        }
+    }
+}
+
+fn main() {}
@@ -0,0 +1,93 @@
+use declarative_state_machine::state_machine;
+
+state_machine! {
+    machine pump {
+        event run(_old: &mut State, _new: &mut State) {}
+
+        event pause(_old: &mut State, _new: &mut State) {}
+
+        event stop(_old: &mut State, _new: &mut State) {}
+
+        event fail(_old: &mut State, _new: &mut State) {}
+
+        event reset(_old: &mut State, _new: &mut State) {}
+
+        states {
+            Idle,
+            Running,
+            Paused,
+            Stopped,
+            Error,
+        }
+
+        transitions {
+            Idle              => Running : run;
+            Running           => Paused  : pause;
+            Paused | Running  => Stopped : stop;
+            * - Idle | Error  => Error   : fail;
+            *                 => Idle    : reset;
+            Error             => Stopped : reset;
+        }
+    }
+}
+
+use pump::{Machine, StateIdentifier, TransitionErrorKind};
+
+fn running() -> Machine {
+    let mut machine = Machine::new();
+    machine.run().unwrap();
+    machine
+}
+
+#[test]
+fn multiple_sources_apply_to_each_source() {
+    let mut machine = running();
+    machine.stop().unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Stopped);
+
+    let mut machine = running();
+    machine.pause().unwrap();
+    machine.stop().unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Stopped);
+
+    let mut machine = Machine::new();
+    assert_eq!(machine.stop().unwrap_err().kind, TransitionErrorKind::NoTransition);
+}
+
+#[test]
+fn wildcard_applies_to_all_states_including_destination() {
+    let mut machine = Machine::new();
+    machine.reset().unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Idle);
+
+    let mut machine = running();
+    machine.reset().unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Idle);
+
+    let mut machine = running();
+    machine.stop().unwrap();
+    machine.reset().unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Idle);
+}
+
+#[test]
+fn wildcard_excludes_listed_states() {
+    let mut machine = running();
+    machine.fail().unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Error);
+
+    assert_eq!(machine.fail().unwrap_err().kind, TransitionErrorKind::NoTransition);
+
+    let mut machine = Machine::new();
+    assert_eq!(machine.fail().unwrap_err().kind, TransitionErrorKind::NoTransition);
+    assert_eq!(machine.state_identifier(), StateIdentifier::Idle);
+}
+
+#[test]
+fn explicit_source_takes_precedence_over_wildcard() {
+    let mut machine = running();
+    machine.fail().unwrap();
+
+    machine.reset().unwrap();
+    assert_eq!(machine.state_identifier(), StateIdentifier::Stopped);
+}